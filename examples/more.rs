@@ -4,6 +4,7 @@ use derive_more::{derive::Into, Add, Display, From};
 #[derive(Debug, Display, From, Add, PartialEq, Into)]
 struct MyInt(i32);
 
+#[allow(dead_code)]
 #[derive(PartialEq, From)]
 struct Point2D {
     x: i32,
@@ -0,0 +1,475 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use reqwest::{header::CONTENT_TYPE, Client, Method, RequestBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+//shortener2 的命令行客户端，通过 HTTP API 管理短链接
+//cargo run --example shorten -- create https://www.rust-lang.org/ --alias rust --ttl 7d
+const DEFAULT_BASE_URL: &str = "http://localhost:9876";
+//不指定 --config 时读取 ~/.config/shorten.toml（如果存在）
+const DEFAULT_CONFIG: &str = ".config/shorten.toml";
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Parser)]
+#[command(about = "command-line client for the url shortener")]
+struct Cli {
+    /// TOML 配置文件，包含 base_url 和 api_key，命令行参数和环境变量优先
+    #[arg(short, long, global = true, env = "SHORTEN_CONFIG")]
+    config: Option<PathBuf>,
+    /// 服务地址，默认为 http://localhost:9876
+    #[arg(long, global = true, env = "SHORTEN_BASE_URL")]
+    base_url: Option<String>,
+    #[arg(long, global = true, env = "SHORTEN_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+    /// 输出格式
+    #[arg(short, long, global = true, value_enum, default_value_t = Output::Table)]
+    output: Output,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// 创建短链接
+    Create {
+        url: String,
+        /// 自定义 id
+        #[arg(long)]
+        alias: Option<String>,
+        /// 有效期，例如 3600、30m、12h、7d
+        #[arg(long, value_parser = parse_ttl)]
+        ttl: Option<u64>,
+        #[arg(long)]
+        title: Option<String>,
+        /// 可以重复指定多个标签
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+    /// 查看短链接
+    Get { id: String },
+    /// 查看点击统计
+    Stats { id: String },
+    /// 列出自己的短链接，按创建时间倒序
+    List {
+        /// 在标题和描述中搜索
+        #[arg(short, long)]
+        query: Option<String>,
+        #[arg(long)]
+        tag: Option<String>,
+        /// 每页条数
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+        /// 按 next_cursor 翻页取出全部
+        #[arg(long)]
+        all: bool,
+    },
+    /// 删除短链接
+    Delete { id: String },
+    /// 从导出的 json 或 csv 文件导入，按扩展名判断格式
+    Import {
+        file: PathBuf,
+        /// 重新生成 id，不保留文件中的 id
+        #[arg(long)]
+        regenerate_ids: bool,
+    },
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    base_url: Option<String>,
+    api_key: Option<String>,
+}
+
+//服务端统一的错误响应
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    code: String,
+    message: String,
+    request_id: Option<String>,
+}
+
+struct Api {
+    client: Client,
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl Api {
+    fn new(cli: &Cli) -> Result<Self> {
+        let file = load_config(cli.config.as_deref())?;
+        //环境变量设置为空字符串时视为未设置
+        let base_url = non_empty(&cli.base_url)
+            .or(file.base_url)
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        let client = Client::builder().timeout(TIMEOUT).build()?;
+        Ok(Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: non_empty(&cli.api_key).or(file.api_key),
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let req = self
+            .client
+            .request(method, format!("{}{}", self.base_url, path));
+        match &self.api_key {
+            Some(key) => req.bearer_auth(key),
+            None => req,
+        }
+    }
+
+    //发送请求，非 2xx 响应转成错误，204 返回 Null
+    async fn send(&self, req: RequestBuilder) -> Result<Value> {
+        let res = req.send().await.context("request failed")?;
+        let status = res.status();
+        let body = res.bytes().await?;
+        if !status.is_success() {
+            return Err(match serde_json::from_slice::<ErrorBody>(&body) {
+                Ok(e) => anyhow!(
+                    "{} ({}, request id {})",
+                    e.message,
+                    e.code,
+                    e.request_id.as_deref().unwrap_or("-")
+                ),
+                Err(_) => anyhow!("{}: {}", status, String::from_utf8_lossy(&body)),
+            });
+        }
+        if body.is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_slice(&body).context("invalid response body")
+    }
+}
+
+//错误只输出一行，不带 backtrace
+#[tokio::main]
+async fn main() {
+    if let Err(e) = run(Cli::parse()).await {
+        eprintln!("error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let api = Api::new(&cli)?;
+    let output = cli.output;
+    match cli.command {
+        Command::Create {
+            url,
+            alias,
+            ttl,
+            title,
+            tags,
+        } => {
+            let mut body = json!({ "url": url });
+            for (key, value) in [
+                ("alias", json!(alias)),
+                ("ttl", json!(ttl)),
+                ("title", json!(title)),
+            ] {
+                if !value.is_null() {
+                    body[key] = value;
+                }
+            }
+            if !tags.is_empty() {
+                body["tags"] = json!(tags);
+            }
+            let ret = api.send(api.request(Method::POST, "/").json(&body)).await?;
+            match output {
+                Output::Json => print_json(&ret)?,
+                Output::Table => println!("{}", text(&ret["url"])),
+            }
+        }
+        Command::Get { id } => {
+            let link = get_link(&api, &id).await?;
+            match output {
+                Output::Json => print_json(&link)?,
+                Output::Table => {
+                    let rows = [
+                        "id",
+                        "url",
+                        "title",
+                        "description",
+                        "tags",
+                        "owner",
+                        "created_at",
+                        "expires_at",
+                        "clicks",
+                        "max_clicks",
+                        "redirect_status",
+                        "disabled_reason",
+                    ]
+                    .iter()
+                    .map(|key| vec![key.to_string(), text(&link[*key])])
+                    .collect();
+                    print_table(&["FIELD", "VALUE"], rows);
+                }
+            }
+        }
+        Command::Stats { id } => {
+            let link = get_link(&api, &id).await?;
+            let stats = json!({
+                "id": link["id"],
+                "clicks": link["clicks"],
+                "max_clicks": link["max_clicks"],
+                "destinations": link["destinations"],
+            });
+            match output {
+                Output::Json => print_json(&stats)?,
+                Output::Table => print_stats(&stats),
+            }
+        }
+        Command::List {
+            query,
+            tag,
+            limit,
+            all,
+        } => {
+            let links = list_links(&api, query, tag, limit, all).await?;
+            match output {
+                Output::Json => print_json(&Value::Array(links))?,
+                Output::Table => {
+                    let rows = links
+                        .iter()
+                        .map(|link| {
+                            vec![
+                                text(&link["id"]),
+                                text(&link["clicks"]),
+                                text(&link["created_at"]),
+                                text(&link["title"]),
+                                text(&link["url"]),
+                            ]
+                        })
+                        .collect();
+                    print_table(&["ID", "CLICKS", "CREATED", "TITLE", "URL"], rows);
+                }
+            }
+        }
+        Command::Delete { id } => {
+            api.send(api.request(Method::DELETE, &format!("/api/links/{}", id)))
+                .await?;
+            match output {
+                Output::Json => print_json(&json!({ "deleted": id }))?,
+                Output::Table => println!("deleted {}", id),
+            }
+        }
+        Command::Import {
+            file,
+            regenerate_ids,
+        } => {
+            let ret = import(&api, &file, regenerate_ids).await?;
+            match output {
+                Output::Json => print_json(&ret)?,
+                Output::Table => print_import(&ret),
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn get_link(api: &Api, id: &str) -> Result<Value> {
+    api.send(api.request(Method::GET, &format!("/api/links/{}", id)))
+        .await
+}
+
+//all 为 true 时沿着 next_cursor 一直取到最后一页
+async fn list_links(
+    api: &Api,
+    query: Option<String>,
+    tag: Option<String>,
+    limit: u32,
+    all: bool,
+) -> Result<Vec<Value>> {
+    let mut links = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut params = vec![("limit", limit.to_string())];
+        params.extend(query.clone().map(|q| ("q", q)));
+        params.extend(tag.clone().map(|t| ("tag", t)));
+        params.extend(cursor.take().map(|c| ("cursor", c)));
+        let mut page = api
+            .send(api.request(Method::GET, "/api/links").query(&params))
+            .await?;
+        if let Value::Array(items) = page["links"].take() {
+            links.extend(items);
+        }
+        match page["next_cursor"].as_str() {
+            Some(next) if all => cursor = Some(next.to_string()),
+            _ => return Ok(links),
+        }
+    }
+}
+
+async fn import(api: &Api, file: &Path, regenerate_ids: bool) -> Result<Value> {
+    let body = fs::read(file).with_context(|| format!("failed to read {}", file.display()))?;
+    let is_csv = file
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    let content_type = if is_csv {
+        "text/csv"
+    } else {
+        "application/json"
+    };
+    let ids = if regenerate_ids {
+        "regenerate"
+    } else {
+        "preserve"
+    };
+    let req = api
+        .request(Method::POST, "/api/import")
+        .query(&[("ids", ids)])
+        .header(CONTENT_TYPE, content_type)
+        .body(body);
+    api.send(req).await
+}
+
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value.clone().filter(|v| !v.is_empty())
+}
+
+fn load_config(path: Option<&Path>) -> Result<FileConfig> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match std::env::var_os("HOME") {
+            Some(home) if Path::new(&home).join(DEFAULT_CONFIG).exists() => {
+                Path::new(&home).join(DEFAULT_CONFIG)
+            }
+            _ => return Ok(FileConfig::default()),
+        },
+    };
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("failed to read config {}", path.display()))?;
+    toml::from_str(&content).with_context(|| format!("invalid config {}", path.display()))
+}
+
+//秒数，或者带 s、m、h、d 后缀
+fn parse_ttl(value: &str) -> Result<u64, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 24 * 3600,
+        _ => {
+            return Err(format!(
+                "unknown ttl unit {:?}, expected s, m, h or d",
+                unit
+            ))
+        }
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("invalid ttl {:?}", value))
+}
+
+fn print_json(value: &Value) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn print_stats(stats: &Value) {
+    println!("clicks: {}", text(&stats["clicks"]));
+    if let Some(max) = stats["max_clicks"].as_i64() {
+        let left = max - stats["clicks"].as_i64().unwrap_or_default();
+        println!("max_clicks: {} ({} left)", max, left.max(0));
+    }
+    let Some(destinations) = stats["destinations"].as_array().filter(|d| !d.is_empty()) else {
+        return;
+    };
+    let total: i64 = destinations
+        .iter()
+        .filter_map(|d| d["clicks"].as_i64())
+        .sum();
+    let rows = destinations
+        .iter()
+        .map(|d| {
+            let clicks = d["clicks"].as_i64().unwrap_or_default();
+            vec![
+                text(&d["url"]),
+                text(&d["weight"]),
+                clicks.to_string(),
+                format!("{}%", clicks * 100 / total.max(1)),
+            ]
+        })
+        .collect();
+    println!();
+    print_table(&["DESTINATION", "WEIGHT", "CLICKS", "SHARE"], rows);
+}
+
+fn print_import(ret: &Value) {
+    let rows = ret["results"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|r| match r.get("error") {
+            Some(e) => vec![
+                text(&r["index"]),
+                "error".to_string(),
+                format!("{} {}", text(&e["status"]), text(&e["message"])),
+            ],
+            None => vec![text(&r["index"]), text(&r["id"]), text(&r["url"])],
+        })
+        .collect();
+    print_table(&["ROW", "ID", "URL"], rows);
+    println!(
+        "\n{} succeeded, {} failed",
+        text(&ret["succeeded"]),
+        text(&ret["failed"])
+    );
+}
+
+//字符串不带引号，null 为空，数组用逗号连接
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(text).collect::<Vec<_>>().join(","),
+        v => v.to_string(),
+    }
+}
+
+//按每列最长的值对齐，最后一列不补空格
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let last = cells.len().saturating_sub(1);
+        let cells: Vec<String> = cells
+            .into_iter()
+            .enumerate()
+            .map(|(i, cell)| match i == last {
+                true => cell.to_string(),
+                false => format!("{}{}", cell, " ".repeat(widths[i] - cell.chars().count())),
+            })
+            .collect();
+        println!("{}", cells.join("  "));
+    };
+    line(headers.to_vec());
+    for row in &rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
@@ -2,23 +2,33 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use axum::{
-    extract::{Path, State},
+    extract::State,
+    middleware,
     response::IntoResponse,
     routing::{get, post},
     Json,
 };
+use error::{not_found, request_id, AppJson, AppPath, MyError};
 use http::{header::LOCATION, HeaderMap, StatusCode};
 use nanoid::nanoid;
 use serde::{Deserialize, Serialize};
 use sqlx::{prelude::FromRow, PgPool};
 use tokio::net::TcpListener;
-use tracing::{info, level_filters::LevelFilter, warn};
+use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{
     fmt::{format::FmtSpan, Layer},
     layer::SubscriberExt,
     util::SubscriberInitExt as _,
     Layer as _,
 };
+//和 shortener2 使用同一套错误响应体
+#[allow(dead_code)]
+#[path = "shortener2/error.rs"]
+mod error;
+
+//与 shortener2 的 store/postgres.rs 中的 SHARED_URL 相同
+const SHARED_URL: &str = "interstitial = false AND redirect_status = 302 AND query_params = '{}'::jsonb AND password_hash IS NULL AND max_clicks IS NULL AND destinations = '[]'::jsonb AND title IS NULL AND description IS NULL AND tags = '{}' AND expires_at IS NULL AND rules = '[]'::jsonb";
+
 #[derive(Debug)]
 struct AppState {
     db: PgPool,
@@ -56,6 +66,8 @@ async fn main() -> Result<()> {
     let app = axum::Router::new()
         .route("/", post(shorten))
         .route("/:id", get(redirect))
+        .fallback(not_found)
+        .layer(middleware::from_fn(request_id))
         .with_state(state);
 
     let listener = TcpListener::bind(addr).await?;
@@ -66,12 +78,9 @@ async fn main() -> Result<()> {
 
 async fn shorten(
     State(state): State<Arc<AppState>>,
-    Json(data): Json<ShortenRequest>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let id = state.shorten(&data.url).await.map_err(|e| {
-        warn!("Failed to shorten url: {}", e);
-        StatusCode::UNPROCESSABLE_ENTITY
-    })?;
+    AppJson(data): AppJson<ShortenRequest>,
+) -> Result<impl IntoResponse, MyError> {
+    let id = state.shorten(&data.url).await?;
     let body = Json(ShortenResponse {
         url: format!("http://localhost:9876/{}", id),
     });
@@ -79,47 +88,45 @@ async fn shorten(
 }
 
 async fn redirect(
-    Path(id): Path<String>,
+    AppPath(id): AppPath<String>,
     State(state): State<Arc<AppState>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let url = state
-        .get_url(&id)
-        .await
-        .map_err(|_| StatusCode::NOT_FOUND)?;
+) -> Result<impl IntoResponse, MyError> {
+    let url = state.get_url(&id).await?;
     let mut headers = HeaderMap::new();
-    headers.insert(LOCATION, url.parse().unwrap());
+    let location = url
+        .parse()
+        .map_err(|e| MyError::Internal(format!("invalid location for {}: {}", id, e)))?;
+    headers.insert(LOCATION, location);
     Ok((headers, StatusCode::FOUND))
 }
 
 impl AppState {
     async fn new(db_url: &str) -> Result<Self> {
         let db = PgPool::connect(db_url).await?;
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS urls (
-                id CHAR(6) PRIMARY KEY,
-                url TEXT NOT NULL UNIQUE
-            )",
-        )
-        .execute(&db)
-        .await?;
+        //和 shortener2 共用 migrations 目录下的表结构
+        sqlx::migrate!().run(&db).await?;
         Ok(Self { db })
     }
-    async fn shorten(&self, url: &str) -> Result<String> {
+    async fn shorten(&self, url: &str) -> Result<String, MyError> {
         let id = nanoid!(6);
-        let ret: UrlRecord = sqlx::query_as(
-            "INSERT INTO urls (id, url) VALUES ($1, $2) ON CONFLICT (url) DO UPDATE SET url=excluded.url RETURNING id",
-        )
+        //按 url 去重依赖 migrations 中最新的 short_urls_url_shared_idx，条件必须与索引的条件一致
+        //这里写入的都是默认域名下使用默认配置的普通短链接，owner 为空字符串，NULL 在唯一索引中互不冲突
+        let ret: UrlRecord = sqlx::query_as(&format!(
+            "INSERT INTO short_urls (id, url, owner) VALUES ($1, $2, '') ON CONFLICT (domain, owner, url) WHERE {} DO UPDATE SET url=excluded.url RETURNING id",
+            SHARED_URL
+        ))
         .bind(&id)
         .bind(url)
         .fetch_one(&self.db)
         .await?;
         Ok(ret.id)
     }
-    async fn get_url(&self, id: &str) -> Result<String> {
-        let url: UrlRecord = sqlx::query_as("SELECT url FROM urls WHERE id = $1")
+    async fn get_url(&self, id: &str) -> Result<String, MyError> {
+        let url: Option<UrlRecord> = sqlx::query_as("SELECT url FROM short_urls WHERE id = $1")
             .bind(id)
-            .fetch_one(&self.db)
+            .fetch_optional(&self.db)
             .await?;
-        Ok(url.url)
+        url.map(|u| u.url)
+            .ok_or_else(|| MyError::UrlNotFound(id.to_string()))
     }
 }
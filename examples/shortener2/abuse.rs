@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::{
+    api::ListQuery,
+    auth::AdminUser,
+    error::{AppJson, AppPath, AppQuery},
+    host::Host,
+    store::{LinkRecord, Report},
+    DbState, MyError,
+};
+
+const MAX_REPORT_LEN: usize = 1000;
+
+//禁用原因，abuse 跳转返回 410，legal 跳转返回 451
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DisableReason {
+    Abuse,
+    Legal,
+}
+
+impl DisableReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisableReason::Abuse => "abuse",
+            DisableReason::Legal => "legal",
+        }
+    }
+}
+
+//已禁用的链接不再跳转
+pub fn check_enabled(link: &LinkRecord) -> Result<(), MyError> {
+    match link.disabled_reason.as_deref() {
+        None => Ok(()),
+        Some("legal") => Err(MyError::UnavailableForLegalReasons(link.id.clone())),
+        Some(_) => Err(MyError::Gone(link.id.clone())),
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ReportRequest {
+    reason: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DisableRequest {
+    reason: DisableReason,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReportList {
+    reports: Vec<Report>,
+    limit: i64,
+    offset: i64,
+}
+
+//POST /:id/report 任何人都可以举报，不需要 api key，和创建短链接共用限流
+#[utoipa::path(
+    post,
+    path = "/{id}/report",
+    tag = "abuse",
+    params(("id" = String, Path, description = "short link id")),
+    request_body = ReportRequest,
+    responses(
+        (status = 202, description = "report recorded", body = Report),
+        (status = 400, description = "reason is empty or too long", body = ErrorBody),
+        (status = 404, description = "short link not found", body = ErrorBody),
+        (status = 429, description = "rate limited, see Retry-After", body = ErrorBody),
+    )
+)]
+pub async fn report_link(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<ReportRequest>,
+) -> Result<impl IntoResponse, MyError> {
+    let reason = payload.reason.trim();
+    if reason.is_empty() || reason.chars().count() > MAX_REPORT_LEN {
+        return Err(MyError::BadRequest(format!(
+            "reason must be between 1 and {} characters",
+            MAX_REPORT_LEN
+        )));
+    }
+    let report = state
+        .store
+        .add_report(&host.name, &id, reason)
+        .await?
+        .ok_or(MyError::UrlNotFound(id))?;
+    Ok((StatusCode::ACCEPTED, Json(report)))
+}
+
+//GET /api/admin/reports 按时间倒序列出所有域名的举报
+#[utoipa::path(
+    get,
+    path = "/api/admin/reports",
+    tag = "admin",
+    params(ListQuery),
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "reports, newest first", body = ReportList),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+        (status = 403, description = "admin api key required", body = ErrorBody),
+    )
+)]
+pub async fn list_reports(
+    State(state): State<Arc<DbState>>,
+    _admin: AdminUser,
+    AppQuery(query): AppQuery<ListQuery>,
+) -> Result<impl IntoResponse, MyError> {
+    let (limit, offset) = query.page();
+    let reports = state.store.list_reports(limit, offset).await?;
+    Ok(Json(ReportList {
+        reports,
+        limit,
+        offset,
+    }))
+}
+
+//PUT /api/admin/links/:id/disabled 禁用链接
+#[utoipa::path(
+    put,
+    path = "/api/admin/links/{id}/disabled",
+    tag = "admin",
+    params(("id" = String, Path, description = "short link id")),
+    request_body = DisableRequest,
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "disabled link", body = LinkRecord),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+        (status = 403, description = "admin api key required", body = ErrorBody),
+        (status = 404, description = "short link not found", body = ErrorBody),
+    )
+)]
+pub async fn disable_link(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    AdminUser(admin): AdminUser,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<DisableRequest>,
+) -> Result<impl IntoResponse, MyError> {
+    info!(
+        "link {} disabled by {}: {}",
+        id,
+        admin.owner,
+        payload.reason.as_str()
+    );
+    let link = state
+        .store
+        .set_disabled(&host.name, &id, Some(payload.reason.as_str()))
+        .await?
+        .ok_or(MyError::UrlNotFound(id))?;
+    Ok(Json(link))
+}
+
+//DELETE /api/admin/links/:id/disabled 恢复链接
+#[utoipa::path(
+    delete,
+    path = "/api/admin/links/{id}/disabled",
+    tag = "admin",
+    params(("id" = String, Path, description = "short link id")),
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "enabled link", body = LinkRecord),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+        (status = 403, description = "admin api key required", body = ErrorBody),
+        (status = 404, description = "short link not found", body = ErrorBody),
+    )
+)]
+pub async fn enable_link(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    AdminUser(admin): AdminUser,
+    AppPath(id): AppPath<String>,
+) -> Result<impl IntoResponse, MyError> {
+    info!("link {} enabled by {}", id, admin.owner);
+    let link = state
+        .store
+        .set_disabled(&host.name, &id, None)
+        .await?
+        .ok_or(MyError::UrlNotFound(id))?;
+    Ok(Json(link))
+}
@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    auth::AuthUser,
+    error::{AppJson, AppPath, AppQuery},
+    host::Host,
+    store::{Cursor, LinkFilter, LinkRecord},
+    validate::normalize_tag,
+    DbState, MyError,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl ListQuery {
+    //返回修正到合法范围内的 (limit, offset)
+    pub fn page(&self) -> (i64, i64) {
+        page(self.limit, self.offset)
+    }
+}
+
+fn page(limit: Option<i64>, offset: Option<i64>) -> (i64, i64) {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    (limit, offset.unwrap_or(0).max(0))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LinkListQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+    //在标题和描述中搜索，多个词需要同时出现
+    q: Option<String>,
+    //只列出带有这个标签的短链接
+    tag: Option<String>,
+    //上一页响应中的 next_cursor，翻页时推荐使用它代替 offset
+    cursor: Option<String>,
+}
+
+impl LinkListQuery {
+    fn filter(&self) -> Result<LinkFilter, MyError> {
+        Ok(LinkFilter {
+            q: self
+                .q
+                .as_deref()
+                .map(str::trim)
+                .filter(|q| !q.is_empty())
+                .map(String::from),
+            tag: self.tag.as_deref().map(normalize_tag).transpose()?,
+            after: self.cursor.as_deref().map(decode_cursor).transpose()?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LinkList {
+    links: Vec<LinkRecord>,
+    limit: i64,
+    offset: i64,
+    //还有下一页时，作为下一次请求的 cursor 参数，为空表示已经是最后一页
+    next_cursor: Option<String>,
+}
+
+//游标对客户端是不透明的字符串，内容为 "<created_at> <id>"
+fn encode_cursor(cursor: &Cursor) -> String {
+    let raw = format!(
+        "{} {}",
+        cursor
+            .created_at
+            .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        cursor.id
+    );
+    URL_SAFE_NO_PAD.encode(raw)
+}
+
+fn decode_cursor(value: &str) -> Result<Cursor, MyError> {
+    let invalid = || MyError::BadRequest(format!("invalid cursor: {}", value));
+    let raw = URL_SAFE_NO_PAD.decode(value).map_err(|_| invalid())?;
+    let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+    let (created_at, id) = raw.split_once(' ').ok_or_else(invalid)?;
+    Ok(Cursor {
+        created_at: DateTime::parse_from_rfc3339(created_at)
+            .map_err(|_| invalid())?
+            .to_utc(),
+        id: id.to_string(),
+    })
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateLinkRequest {
+    url: String,
+}
+
+//GET /api/links?q=&tag=&cursor= 列出或者搜索调用者在当前域名下的短链接，/api 下的接口都按 Host 区分域名
+//搜索结果同样按创建时间倒序，不按相关度排序，这样游标翻页才是稳定的
+#[utoipa::path(
+    get,
+    path = "/api/links",
+    tag = "api",
+    params(LinkListQuery),
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "matching links owned by the caller, newest first", body = LinkList),
+        (status = 400, description = "invalid tag or cursor", body = ErrorBody),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+    )
+)]
+pub async fn list_links(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    user: AuthUser,
+    AppQuery(query): AppQuery<LinkListQuery>,
+) -> Result<impl IntoResponse, MyError> {
+    let (limit, offset) = page(query.limit, query.offset);
+    let filter = query.filter()?;
+    //多取一条判断是否还有下一页
+    let mut links = state
+        .list_links(&host.name, &user.owner, &filter, limit + 1, offset)
+        .await?;
+    let next_cursor = match links.len() as i64 > limit {
+        true => {
+            links.truncate(limit as usize);
+            links.last().map(|link| encode_cursor(&Cursor::of(link)))
+        }
+        false => None,
+    };
+    Ok(Json(LinkList {
+        links,
+        limit,
+        offset,
+        next_cursor,
+    }))
+}
+
+//GET /api/links/:id 查看自己的短链接，多目标短链接包含每个目标的点击数
+#[utoipa::path(
+    get,
+    path = "/api/links/{id}",
+    tag = "api",
+    params(("id" = String, Path, description = "short link id")),
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "the link with its click counts", body = LinkRecord),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+        (status = 403, description = "link is owned by someone else", body = ErrorBody),
+        (status = 404, description = "short link not found", body = ErrorBody),
+    )
+)]
+pub async fn get_link(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    user: AuthUser,
+    AppPath(id): AppPath<String>,
+) -> Result<impl IntoResponse, MyError> {
+    let link = state.check_owner(&host.name, &id, &user.owner).await?;
+    Ok(Json(link))
+}
+
+//DELETE /api/links/:id 只有归属人可以删除
+#[utoipa::path(
+    delete,
+    path = "/api/links/{id}",
+    tag = "api",
+    params(("id" = String, Path, description = "short link id")),
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 204, description = "link deleted"),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+        (status = 403, description = "link is owned by someone else", body = ErrorBody),
+        (status = 404, description = "short link not found", body = ErrorBody),
+    )
+)]
+pub async fn delete_link(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    user: AuthUser,
+    AppPath(id): AppPath<String>,
+) -> Result<impl IntoResponse, MyError> {
+    state.delete_link(&host.name, &id, &user.owner).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+//PATCH /api/links/:id 只有归属人可以修改目标 url，修改记录见 /api/links/:id/history
+#[utoipa::path(
+    patch,
+    path = "/api/links/{id}",
+    tag = "api",
+    params(("id" = String, Path, description = "short link id")),
+    request_body = UpdateLinkRequest,
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "updated link", body = LinkRecord),
+        (status = 400, description = "invalid url, or the link has several destinations", body = ErrorBody),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+        (status = 403, description = "link is owned by someone else", body = ErrorBody),
+        (status = 404, description = "short link not found", body = ErrorBody),
+        (status = 409, description = "url is already used by another link", body = ErrorBody),
+    )
+)]
+pub async fn update_link(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    user: AuthUser,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<UpdateLinkRequest>,
+) -> Result<impl IntoResponse, MyError> {
+    let url = state.normalize_url(&payload.url)?;
+    let link = state
+        .update_link(&host.name, &id, &user.owner, &url)
+        .await?;
+    Ok(Json(link))
+}
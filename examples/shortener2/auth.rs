@@ -0,0 +1,127 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{async_trait, extract::FromRequestParts};
+use http::{header::AUTHORIZATION, request::Parts, HeaderMap};
+use serde::Deserialize;
+
+use crate::{DbState, MyError};
+
+const API_KEY_HEADER: &str = "x-api-key";
+
+//配置中的一条 API key
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeyEntry {
+    pub owner: String,
+    pub key: String,
+    //管理员可以调用 /api/admin 下的接口
+    #[serde(default)]
+    pub admin: bool,
+}
+
+impl ApiKeyEntry {
+    //解析形如 "alice:key1,bob:key2,root:key3:admin" 的配置
+    pub fn parse_list(spec: &str) -> Result<Vec<Self>, MyError> {
+        spec.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|entry| {
+                let (rest, admin) = match entry.strip_suffix(":admin") {
+                    Some(rest) => (rest, true),
+                    None => (entry, false),
+                };
+                rest.split_once(':')
+                    .filter(|(owner, key)| !owner.is_empty() && !key.is_empty())
+                    .map(|(owner, key)| Self {
+                        owner: owner.to_string(),
+                        key: key.to_string(),
+                        admin,
+                    })
+                    .ok_or_else(|| MyError::Config(format!("invalid api key entry: {}", entry)))
+            })
+            .collect()
+    }
+}
+
+//API key 与所属用户的映射，只保存 key 的 blake3 哈希，不在内存中保留明文
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    users: HashMap<blake3::Hash, AuthUser>,
+}
+
+impl ApiKeys {
+    pub fn new(entries: &[ApiKeyEntry]) -> Self {
+        let users = entries
+            .iter()
+            .map(|e| {
+                let user = AuthUser {
+                    owner: e.owner.clone(),
+                    admin: e.admin,
+                };
+                (blake3::hash(e.key.as_bytes()), user)
+            })
+            .collect();
+        Self { users }
+    }
+
+    pub fn user(&self, key: &str) -> Option<&AuthUser> {
+        self.users.get(&blake3::hash(key.as_bytes()))
+    }
+}
+
+//通过 Authorization: Bearer <key> 或 X-API-Key: <key> 认证的调用者
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub owner: String,
+    pub admin: bool,
+}
+
+//配置中 admin = true 的调用者
+#[derive(Debug, Clone)]
+pub struct AdminUser(pub AuthUser);
+
+#[async_trait]
+impl FromRequestParts<Arc<DbState>> for AuthUser {
+    type Rejection = MyError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<DbState>,
+    ) -> Result<Self, Self::Rejection> {
+        let key = api_key(&parts.headers)
+            .ok_or_else(|| MyError::Unauthorized("missing api key".to_string()))?;
+        state
+            .api_keys
+            .user(key)
+            .cloned()
+            .ok_or_else(|| MyError::Unauthorized("invalid api key".to_string()))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<DbState>> for AdminUser {
+    type Rejection = MyError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<DbState>,
+    ) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        match user.admin {
+            true => Ok(Self(user)),
+            false => Err(MyError::Forbidden("admin api key required".to_string())),
+        }
+    }
+}
+
+//请求中携带的 api key，未校验是否有效
+pub fn api_key(headers: &HeaderMap) -> Option<&str> {
+    bearer_token(headers)
+        .or_else(|| headers.get(API_KEY_HEADER)?.to_str().ok())
+        .map(str::trim)
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then_some(token)
+}
@@ -0,0 +1,260 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{body::Bytes, extract::State, response::IntoResponse, Json};
+use http::{header::CONTENT_TYPE, HeaderMap};
+use serde::Serialize;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::{auth::AuthUser, host::Host, DbState, MyError, UrlRequest};
+
+//批量接口的请求体上限，默认的 2MB 放不下上万条 url
+pub const MAX_BATCH_BODY: usize = 16 * 1024 * 1024;
+pub const MAX_BATCH_SIZE: usize = 10_000;
+//每个分块一条 INSERT 语句
+const CHUNK_SIZE: usize = 500;
+const NDJSON: &str = "application/x-ndjson";
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchResponse {
+    succeeded: usize,
+    failed: usize,
+    results: Vec<BatchItem>,
+}
+
+impl BatchResponse {
+    pub fn new(results: Vec<BatchItem>) -> Self {
+        let failed = results.iter().filter(|r| r.error.is_some()).count();
+        Self {
+            succeeded: results.len() - failed,
+            failed,
+            results,
+        }
+    }
+}
+
+//每一条 url 的处理结果，成功时带 id 和短链接，失败时带 error
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchItem {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<BatchError>,
+}
+
+impl BatchItem {
+    pub fn new(host: &Host, index: usize, ret: Result<String, BatchError>) -> Self {
+        match ret {
+            Ok(id) => Self {
+                index,
+                url: Some(host.short_url(&id)),
+                id: Some(id),
+                error: None,
+            },
+            Err(error) => Self {
+                index,
+                id: None,
+                url: None,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+//与统一错误响应体使用相同的 code 和 message
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BatchError {
+    status: u16,
+    code: &'static str,
+    message: String,
+}
+
+impl From<&MyError> for BatchError {
+    fn from(e: &MyError) -> Self {
+        Self {
+            status: e.status_code().as_u16(),
+            code: e.code(),
+            message: e.message(),
+        }
+    }
+}
+
+//POST /batch 请求体为 UrlRequest 数组，或者 Content-Type 为 application/x-ndjson 时每行一个 UrlRequest
+//每一条的 alias 和可选配置与 POST / 相同，单条 url 出错不影响其他 url，结果按请求中的顺序返回
+#[utoipa::path(
+    post,
+    path = "/batch",
+    tag = "links",
+    request_body(
+        content = Vec<UrlRequest>,
+        description = "json array, or one UrlRequest per line with Content-Type: application/x-ndjson",
+        content_type = "application/json",
+    ),
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "per-url results in request order", body = BatchResponse),
+        (status = 400, description = "invalid body or too many urls", body = ErrorBody),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+        (status = 429, description = "rate limited, see Retry-After", body = ErrorBody),
+    )
+)]
+pub async fn shorten_batch(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    user: AuthUser,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, MyError> {
+    let requests = parse_body(&headers, &body)?;
+    if requests.len() > MAX_BATCH_SIZE {
+        return Err(MyError::BadRequest(format!(
+            "batch is larger than {} urls",
+            MAX_BATCH_SIZE
+        )));
+    }
+    //先逐条校验，普通短链接留到后面按 url 分块写入，其他的逐条创建
+    let mut items = Vec::with_capacity(requests.len());
+    for req in requests {
+        items.push(match req {
+            Ok(req) => prepare(&state, &host, &user.owner, req).await,
+            Err(e) => Item::Done(Err(e)),
+        });
+    }
+
+    let mut pending: Vec<String> = items
+        .iter()
+        .filter_map(|item| match item {
+            Item::Shared(url) => Some(url.clone()),
+            Item::Done(_) => None,
+        })
+        .collect();
+    pending.sort();
+    pending.dedup();
+
+    //分块写入，某个分块失败只影响该分块中的 url
+    let mut ids = HashMap::new();
+    let mut failures = HashMap::new();
+    for chunk in pending.chunks(CHUNK_SIZE) {
+        match state.shorten_many(&host.name, chunk, &user.owner).await {
+            Ok(ret) => ids.extend(ret),
+            Err(e) => {
+                error!("failed to shorten batch chunk: {}", e);
+                let error = BatchError::from(&e);
+                failures.extend(chunk.iter().map(|url| (url.clone(), error.clone())));
+            }
+        }
+    }
+
+    let results: Vec<BatchItem> = items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let ret = match item {
+                Item::Done(ret) => ret.map_err(|e| BatchError::from(&e)),
+                Item::Shared(url) => match (ids.get(&url), failures.get(&url)) {
+                    (Some(id), _) => Ok(id.clone()),
+                    (None, Some(error)) => Err(error.clone()),
+                    (None, None) => Err(BatchError::from(&MyError::UrlNotFound(url))),
+                },
+            };
+            BatchItem::new(&host, index, ret)
+        })
+        .collect();
+    Ok(Json(BatchResponse::new(results)))
+}
+
+//一条请求的处理方式
+enum Item {
+    //普通短链接，规范化后的 url，和同一批中的其他普通短链接一起写入
+    Shared(String),
+    //校验失败，或者已经像 POST / 一样单独创建
+    Done(Result<String, MyError>),
+}
+
+//与 POST / 使用相同的校验和配置，批量接口不返回二维码
+async fn prepare(state: &DbState, host: &Host, owner: &str, req: UrlRequest) -> Item {
+    if req.qr {
+        return Item::Done(Err(MyError::BadRequest(
+            "qr is not supported in batches".to_string(),
+        )));
+    }
+    let link = match state.new_link(&host.name, &req.url, owner.to_string(), req.options) {
+        Ok(link) => link,
+        Err(e) => return Item::Done(Err(e)),
+    };
+    match req.alias {
+        Some(alias) => Item::Done(state.shorten_as(&alias, &link).await),
+        None if link.is_shared() => Item::Shared(link.url),
+        None => Item::Done(state.shorten(&link).await),
+    }
+}
+
+//解析请求体，ndjson 中无法解析的行作为单条失败返回，不影响整批
+fn parse_body(
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Vec<Result<UrlRequest, MyError>>, MyError> {
+    let is_ndjson = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with(NDJSON));
+    if !is_ndjson {
+        let requests: Vec<UrlRequest> = serde_json::from_slice(body)
+            .map_err(|e| MyError::BadRequest(format!("invalid batch body: {}", e)))?;
+        return Ok(requests.into_iter().map(Ok).collect());
+    }
+    let body = std::str::from_utf8(body)
+        .map_err(|e| MyError::BadRequest(format!("invalid batch body: {}", e)))?;
+    Ok(body
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            serde_json::from_str(line)
+                .map_err(|e| MyError::BadRequest(format!("invalid json line: {}", e)))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Request, StatusCode};
+    use serde_json::json;
+
+    use crate::testing::{self, send};
+
+    #[tokio::test]
+    async fn batch_items_keep_their_options() {
+        let state = testing::state(testing::config());
+        let res = send(
+            &state,
+            Request::post("/batch"),
+            Some(json!([
+                { "url": "https://www.rust-lang.org/" },
+                { "url": "https://www.rust-lang.org/" },
+                { "url": "https://crates.io/", "alias": "crates" },
+                { "url": "https://www.rust-lang.org/", "title": "Rust", "tags": ["lang"] },
+                { "url": "https://docs.rs/", "max_clicks": 0 },
+                { "url": "https://docs.rs/", "qr": true },
+            ])),
+        )
+        .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        let results = res.json()["results"].as_array().unwrap().clone();
+        let id = |i: usize| results[i]["id"].as_str().unwrap_or_default().to_string();
+
+        //普通短链接仍然按 url 去重，带配置的单独创建
+        assert_eq!(id(0), id(1));
+        assert_eq!(id(2), "crates");
+        assert_ne!(id(3), id(0));
+        let link = state.store.get_link("", &id(3)).await.unwrap().unwrap();
+        assert_eq!(link.title.as_deref(), Some("Rust"));
+        assert_eq!(link.tags, ["lang"]);
+        for i in [4, 5] {
+            assert_eq!(results[i]["error"]["code"], "bad_request", "{:?}", results);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{preview::domain_matches, MyError};
+
+//屏蔽列表配置，path 为空时不启用
+//文件每行一条规则，# 开头为注释：
+//  evil.com          屏蔽该域名及其子域名
+//  bit.ly/phish*     含 / 或 * 的规则按通配符匹配 host + path + query
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlocklistConfig {
+    pub path: Option<PathBuf>,
+    //检查文件是否修改的间隔（秒）
+    pub reload_secs: u64,
+}
+
+impl Default for BlocklistConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            reload_secs: 5,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Rules {
+    domains: Vec<String>,
+    patterns: Vec<String>,
+}
+
+impl Rules {
+    fn parse(content: &str) -> Self {
+        let mut rules = Self::default();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = line.to_lowercase();
+            if rule.contains('/') || rule.contains('*') {
+                rules.patterns.push(rule);
+            } else {
+                rules.domains.push(rule);
+            }
+        }
+        rules
+    }
+
+    //返回命中的规则
+    fn matches(&self, url: &Url) -> Option<&str> {
+        let host = url.host_str()?.to_lowercase();
+        if let Some(d) = self.domains.iter().find(|d| domain_matches(&host, d)) {
+            return Some(d);
+        }
+        let mut target = format!("{}{}", host, url.path());
+        if let Some(query) = url.query() {
+            target.push('?');
+            target.push_str(query);
+        }
+        let target = target.to_lowercase();
+        self.patterns
+            .iter()
+            .find(|p| wildcard_match(p, &target))
+            .map(String::as_str)
+    }
+
+    fn len(&self) -> usize {
+        self.domains.len() + self.patterns.len()
+    }
+}
+
+//从本地文件加载的屏蔽列表，文件修改后自动重新加载
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    config: BlocklistConfig,
+    rules: RwLock<Arc<Rules>>,
+}
+
+impl Blocklist {
+    //启动时文件读取失败直接报错，运行中重新加载失败只记录日志并沿用旧规则
+    pub fn load(config: &BlocklistConfig) -> Result<Self, MyError> {
+        let rules = match &config.path {
+            Some(path) => {
+                let content = fs::read_to_string(path).map_err(|e| {
+                    MyError::Config(format!(
+                        "failed to read blocklist {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                let rules = Rules::parse(&content);
+                info!(
+                    "loaded {} blocklist rules from {}",
+                    rules.len(),
+                    path.display()
+                );
+                rules
+            }
+            None => Rules::default(),
+        };
+        Ok(Self {
+            config: config.clone(),
+            rules: RwLock::new(Arc::new(rules)),
+        })
+    }
+
+    //url 命中屏蔽规则时返回 Blocked 错误
+    pub fn check(&self, url: &str) -> Result<(), MyError> {
+        let Ok(parsed) = Url::parse(url) else {
+            return Ok(());
+        };
+        let rules = self.rules.read().unwrap().clone();
+        match rules.matches(&parsed) {
+            Some(rule) => Err(MyError::Blocked(format!(
+                "{} matches blocklist rule {}",
+                url, rule
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    //后台定时检查文件修改时间，有变化时重新加载
+    pub fn watch(self: Arc<Self>) {
+        let Some(path) = self.config.path.clone() else {
+            return;
+        };
+        let interval = Duration::from_secs(self.config.reload_secs);
+        tokio::spawn(async move {
+            let mut last_modified = modified(&path);
+            loop {
+                tokio::time::sleep(interval).await;
+                let current = modified(&path);
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+                match fs::read_to_string(&path) {
+                    Ok(content) => {
+                        let rules = Rules::parse(&content);
+                        info!(
+                            "reloaded {} blocklist rules from {}",
+                            rules.len(),
+                            path.display()
+                        );
+                        *self.rules.write().unwrap() = Arc::new(rules);
+                    }
+                    Err(e) => warn!("failed to reload blocklist {}: {}", path.display(), e),
+                }
+            }
+        });
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+//只支持 * 通配符，匹配任意长度的字符
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    //上一个 * 的位置以及它当时对应的 text 位置，用于回溯
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if pi < p.len() && p[pi] == t[ti] {
+            pi += 1;
+            ti += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_any_run_of_characters() {
+        for (pattern, text, expected) in [
+            ("bit.ly/phish*", "bit.ly/phish", true),
+            ("bit.ly/phish*", "bit.ly/phishing?id=1", true),
+            ("bit.ly/phish*", "bit.ly/fish", false),
+            ("*.example.com/login", "a.b.example.com/login", true),
+            ("*.example.com/login", "example.com/login", false),
+            //需要回溯才能匹配
+            ("a*b*c", "aXbYbZc", true),
+            ("a*b*c", "aXbYcZ", false),
+            ("**", "", true),
+            ("x", "", false),
+            ("", "", true),
+        ] {
+            assert_eq!(
+                wildcard_match(pattern, text),
+                expected,
+                "{} {}",
+                pattern,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn domains_block_their_subdomains() {
+        let rules = Rules::parse(
+            "# comment
+Evil.com
+
+bit.ly/phish*
+*/download.exe
+",
+        );
+        assert_eq!(rules.len(), 3);
+        for (url, expected) in [
+            ("https://evil.com/", Some("evil.com")),
+            ("https://WWW.EVIL.COM/x", Some("evil.com")),
+            ("https://a.b.evil.com/", Some("evil.com")),
+            ("https://notevil.com/", None),
+            ("https://evil.com.example.org/", None),
+            ("https://bit.ly/Phishing", Some("bit.ly/phish*")),
+            ("https://bit.ly/other", None),
+            (
+                "https://cdn.example.org/download.exe",
+                Some("*/download.exe"),
+            ),
+            ("https://cdn.example.org/download.exe?v=2", None),
+        ] {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(rules.matches(&url), expected, "{}", url);
+        }
+    }
+}
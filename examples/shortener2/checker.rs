@@ -0,0 +1,353 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use futures::future::join_all;
+use http::StatusCode;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{store::LinkRecord, DbState, MyError};
+
+const USER_AGENT: &str = "shortener2-link-checker";
+//检查时最多跟随的重定向次数
+const MAX_REDIRECTS: usize = 5;
+
+//目标地址可达性检查，后台任务定期对短链接的 url 发送 HEAD 请求
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CheckerConfig {
+    pub enabled: bool,
+    //同一个短链接两次检查的间隔
+    pub interval_secs: u64,
+    //没有需要检查的短链接时，隔多久再查一次
+    pub poll_interval_secs: u64,
+    //每轮最多检查的短链接数，同一轮中的请求并发发送
+    pub batch_size: i64,
+    pub timeout_secs: u64,
+    //连续失败这么多次后标记为 dead
+    pub dead_after: u32,
+    //跳转到 dead 的短链接时先展示目标不可用的页面
+    pub unavailable_page: bool,
+}
+
+impl Default for CheckerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 24 * 3600,
+            poll_interval_secs: 60,
+            batch_size: 20,
+            timeout_secs: 10,
+            dead_after: 3,
+            unavailable_page: false,
+        }
+    }
+}
+
+impl CheckerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval_secs == 0 || self.poll_interval_secs == 0 || self.timeout_secs == 0 {
+            return Err(
+                "checker: interval_secs, poll_interval_secs and timeout_secs must be greater than 0"
+                    .to_string(),
+            );
+        }
+        if !(1..=1000).contains(&self.batch_size) {
+            return Err(format!(
+                "checker.batch_size: must be between 1 and 1000, got {}",
+                self.batch_size
+            ));
+        }
+        if self.dead_after == 0 {
+            return Err("checker.dead_after: must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+//一次检查的结果，请求失败时 status 为空
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl CheckResult {
+    //需要登录、拒绝访问或者限流说明页面还在
+    pub fn is_reachable(&self) -> bool {
+        self.status
+            .is_some_and(|status| (200..400).contains(&status) || matches!(status, 401 | 403 | 429))
+    }
+}
+
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.status, &self.error) {
+            (Some(status), _) => write!(f, "status {}", status),
+            (None, Some(error)) => write!(f, "{}", error),
+            (None, None) => write!(f, "no response"),
+        }
+    }
+}
+
+pub struct Checker {
+    config: CheckerConfig,
+    client: reqwest::Client,
+}
+
+impl Checker {
+    //HTTP 客户端创建失败（例如 TLS 后端不可用）时和配置错误一样在启动时报错
+    pub fn new(config: CheckerConfig) -> Result<Self, MyError> {
+        let client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .redirect(reqwest::redirect::Policy::limited(MAX_REDIRECTS))
+            .build()
+            .map_err(|e| MyError::Config(format!("failed to build checker client: {}", e)))?;
+        Ok(Self { config, client })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    //是否展示目标不可用的页面
+    pub fn shows_unavailable(&self, link: &LinkRecord) -> bool {
+        self.config.unavailable_page && link.dead
+    }
+
+    //先发送 HEAD，不支持 HEAD 的服务器再用 GET 重试，GET 只读取响应头
+    pub async fn check(&self, url: &str) -> CheckResult {
+        let mut ret = self.client.head(url).send().await;
+        if let Ok(res) = &ret {
+            if matches!(
+                res.status(),
+                StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+            ) {
+                ret = self.client.get(url).send().await;
+            }
+        }
+        match ret {
+            Ok(res) => CheckResult {
+                status: Some(res.status().as_u16()),
+                error: None,
+            },
+            Err(e) => CheckResult {
+                status: None,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+//检查一批到期的短链接，返回检查的数量
+pub async fn check_due(state: &DbState) -> Result<usize, MyError> {
+    let checker = &state.checker;
+    let config = &checker.config;
+    let interval = chrono::Duration::seconds(config.interval_secs as i64);
+    let links = state
+        .store
+        .claim_checks(config.batch_size, Utc::now() - interval)
+        .await?;
+    join_all(links.iter().map(|link| async {
+        let result = checker.check(&link.url).await;
+        if !result.is_reachable() {
+            info!("{}/{} is unreachable: {}", link.domain, link.id, result);
+        }
+        if let Err(e) = state
+            .store
+            .record_check(
+                &link.domain,
+                &link.id,
+                &link.url,
+                &result,
+                config.dead_after,
+            )
+            .await
+        {
+            warn!("failed to record check of {}: {}", link.id, e);
+        }
+    }))
+    .await;
+    Ok(links.len())
+}
+
+//后台检查任务，检查过的短链接要过 interval_secs 之后才会再次检查
+pub async fn run(state: Arc<DbState>) {
+    let config = &state.checker.config;
+    loop {
+        let count = match check_due(&state).await {
+            Ok(count) => count as i64,
+            Err(e) => {
+                warn!("failed to check links: {}", e);
+                0
+            }
+        };
+        if count < config.batch_size {
+            tokio::time::sleep(Duration::from_secs(config.poll_interval_secs)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use axum::{
+        response::Redirect,
+        routing::{get, head},
+        Router,
+    };
+    use http::Request;
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        config::AppConfig,
+        testing::{self, send},
+    };
+
+    //本地的目标网站，axum 的 get 路由同时响应 HEAD
+    async fn mock_server(down: Arc<AtomicBool>) -> String {
+        let app = Router::new()
+            .route("/ok", get(|| async { "ok" }))
+            .route("/gone", get(|| async { StatusCode::NOT_FOUND }))
+            .route("/login", get(|| async { StatusCode::UNAUTHORIZED }))
+            .route("/moved", get(|| async { Redirect::permanent("/ok") }))
+            .route(
+                "/no-head",
+                head(|| async { StatusCode::METHOD_NOT_ALLOWED }).get(|| async { "ok" }),
+            )
+            .route(
+                "/flaky",
+                get(move || async move {
+                    match down.load(Ordering::SeqCst) {
+                        true => StatusCode::SERVICE_UNAVAILABLE,
+                        false => StatusCode::OK,
+                    }
+                }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        base
+    }
+
+    fn config() -> CheckerConfig {
+        CheckerConfig {
+            enabled: true,
+            //测试中每一轮都重新检查所有短链接
+            interval_secs: 0,
+            timeout_secs: 5,
+            dead_after: 2,
+            unavailable_page: true,
+            ..CheckerConfig::default()
+        }
+    }
+
+    fn state() -> Arc<DbState> {
+        testing::state(AppConfig {
+            checker: config(),
+            ..testing::config()
+        })
+    }
+
+    async fn create(state: &Arc<DbState>, url: &str) -> String {
+        testing::create(state, json!({ "url": url })).await
+    }
+
+    async fn link(state: &Arc<DbState>, id: &str) -> Value {
+        send(state, Request::get("/api/links"), None).await.json()["links"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|link| link["id"] == id)
+            .cloned()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn responses_are_classified() {
+        let base = mock_server(Arc::default()).await;
+        //绑定后立即释放的端口，连接会被拒绝
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let checker = Checker::new(config()).unwrap();
+        for (path, status, reachable) in [
+            ("/ok", Some(200), true),
+            ("/moved", Some(200), true),
+            ("/no-head", Some(200), true),
+            ("/login", Some(401), true),
+            ("/gone", Some(404), false),
+        ] {
+            let result = checker.check(&format!("{}{}", base, path)).await;
+            assert_eq!(result.status, status, "{}", path);
+            assert_eq!(result.is_reachable(), reachable, "{}", path);
+        }
+        let result = checker.check(&format!("http://{}/", closed)).await;
+        assert_eq!(result.status, None);
+        assert!(result.error.is_some());
+        assert!(!result.is_reachable());
+    }
+
+    #[tokio::test]
+    async fn dead_links_are_flagged_and_show_the_unavailable_page() {
+        let down = Arc::new(AtomicBool::new(true));
+        let base = mock_server(down.clone()).await;
+        let state = state();
+        let flaky = create(&state, &format!("{}/flaky", base)).await;
+        let ok = create(&state, &format!("{}/ok", base)).await;
+
+        assert_eq!(check_due(&state).await.unwrap(), 2);
+        let checked = link(&state, &flaky).await;
+        assert_eq!(checked["check_status"], 503);
+        assert!(checked["checked_at"].is_string());
+        //失败一次还不算 dead
+        assert_eq!(checked["dead"], false);
+
+        check_due(&state).await.unwrap();
+        assert_eq!(link(&state, &flaky).await["dead"], true);
+        assert_eq!(link(&state, &ok).await["dead"], false);
+        let res = send(&state, Request::get(format!("/{}", flaky)), None).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert!(res.location().is_none());
+        assert!(res.body.contains("Destination unavailable"), "{}", res.body);
+        let res = send(&state, Request::get(format!("/{}", ok)), None).await;
+        assert_eq!(res.status, StatusCode::FOUND);
+
+        //恢复后下一次检查清除 dead
+        down.store(false, Ordering::SeqCst);
+        check_due(&state).await.unwrap();
+        let checked = link(&state, &flaky).await;
+        assert_eq!(checked["dead"], false);
+        assert_eq!(checked["check_status"], 200);
+        let res = send(&state, Request::get(format!("/{}", flaky)), None).await;
+        assert_eq!(res.status, StatusCode::FOUND);
+    }
+
+    #[tokio::test]
+    async fn changing_the_url_resets_the_check() {
+        let base = mock_server(Arc::default()).await;
+        let state = state();
+        let id = create(&state, &format!("{}/gone", base)).await;
+        check_due(&state).await.unwrap();
+        check_due(&state).await.unwrap();
+        assert_eq!(link(&state, &id).await["dead"], true);
+
+        let res = send(
+            &state,
+            Request::patch(format!("/api/links/{}", id)),
+            Some(json!({ "url": format!("{}/ok", base) })),
+        )
+        .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        let checked = link(&state, &id).await;
+        assert_eq!(checked["dead"], false);
+        assert!(checked["checked_at"].is_null());
+        assert!(checked["check_status"].is_null());
+    }
+}
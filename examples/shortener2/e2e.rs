@@ -0,0 +1,308 @@
+//端到端测试：和 main 一样用 app(state) 构造服务，监听临时端口，通过真实的 HTTP 请求访问
+//存储使用内存存储，不需要启动 docker-compose 中的 Postgres
+use std::{net::SocketAddr, sync::Arc};
+
+use http::{
+    header::{CONTENT_TYPE, LOCATION},
+    StatusCode,
+};
+use reqwest::{redirect::Policy, RequestBuilder, Response};
+use serde_json::{json, Value};
+use tokio::{net::TcpListener, sync::oneshot};
+
+use crate::{app, config::AppConfig, store::MemoryStore, testing::api_key, DbState};
+
+const ALICE: &str = "key1";
+const BOB: &str = "key2";
+
+//测试用的服务，drop 时优雅关闭
+struct TestServer {
+    base_url: String,
+    client: reqwest::Client,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl TestServer {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let base_url = format!("http://{}", addr);
+        let config = AppConfig {
+            base_url: base_url.clone(),
+            listen_addr: addr,
+            api_keys: [("alice", ALICE), ("bob", BOB)]
+                .into_iter()
+                .map(|(owner, key)| api_key(owner, key))
+                .collect(),
+            ..AppConfig::default()
+        };
+        let state = DbState::with_store(config, Arc::new(MemoryStore::default())).unwrap();
+        //限流需要客户端地址，和 main 一样带上 connect info
+        let service = app(Arc::new(state)).into_make_service_with_connect_info::<SocketAddr>();
+        let (shutdown, stopped) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            axum::serve(listener, service)
+                .with_graceful_shutdown(async {
+                    stopped.await.ok();
+                })
+                .await
+                .unwrap();
+        });
+        Self {
+            base_url,
+            //检查跳转响应本身，不跟随
+            client: reqwest::Client::builder()
+                .redirect(Policy::none())
+                .build()
+                .unwrap(),
+            shutdown: Some(shutdown),
+        }
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.client.get(format!("{}{}", self.base_url, path))
+    }
+
+    fn post(&self, path: &str, key: &str) -> RequestBuilder {
+        self.client
+            .post(format!("{}{}", self.base_url, path))
+            .header("x-api-key", key)
+    }
+
+    //创建短链接，返回响应的状态码和响应体
+    async fn shorten(&self, key: &str, body: Value) -> (StatusCode, Value) {
+        json_body(self.post("/", key).json(&body).send().await.unwrap()).await
+    }
+
+    //创建成功时返回 id
+    async fn shorten_id(&self, key: &str, body: Value) -> String {
+        let (status, body) = self.shorten(key, body).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        let url = body["url"].as_str().unwrap();
+        let id = url
+            .strip_prefix(&format!("{}/", self.base_url))
+            .expect("short url uses base_url");
+        id.to_string()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+    }
+}
+
+async fn json_body(res: Response) -> (StatusCode, Value) {
+    let status = res.status();
+    let body = res.json().await.unwrap_or(Value::Null);
+    (status, body)
+}
+
+//错误响应体的格式：{code, message, request_id, details}
+fn assert_error_body(body: &Value, code: &str) {
+    assert_eq!(body["code"], code, "{}", body);
+    assert!(body["message"].as_str().is_some_and(|m| !m.is_empty()));
+    assert!(body["request_id"].is_string());
+    assert!(body.get("details").is_some());
+}
+
+#[tokio::test]
+async fn created_links_redirect_to_the_url() {
+    let server = TestServer::start().await;
+    let id = server
+        .shorten_id(ALICE, json!({ "url": "https://www.rust-lang.org/learn" }))
+        .await;
+
+    let res = server.get(&format!("/{}", id)).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::FOUND);
+    assert_eq!(res.headers()[LOCATION], "https://www.rust-lang.org/learn");
+
+    //跳转记录了一次点击
+    let (status, link) = json_body(
+        server
+            .get(&format!("/api/links/{}", id))
+            .header("x-api-key", ALICE)
+            .send()
+            .await
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(link["url"], "https://www.rust-lang.org/learn");
+    assert_eq!(link["owner"], "alice");
+    assert_eq!(link["clicks"], 1);
+}
+
+#[tokio::test]
+async fn the_same_url_is_shortened_once() {
+    let server = TestServer::start().await;
+    let id = server
+        .shorten_id(ALICE, json!({ "url": "https://www.rust-lang.org/" }))
+        .await;
+    //规范化之后相同的 url 也复用同一个 id
+    for url in [
+        "https://www.rust-lang.org/",
+        "HTTPS://WWW.Rust-Lang.org/#top",
+    ] {
+        let again = server.shorten_id(ALICE, json!({ "url": url })).await;
+        assert_eq!(again, id, "{}", url);
+    }
+    //带标题的短链接总是单独创建
+    let titled = server
+        .shorten_id(
+            ALICE,
+            json!({ "url": "https://www.rust-lang.org/", "title": "Rust" }),
+        )
+        .await;
+    assert_ne!(titled, id);
+    let other = server
+        .shorten_id(ALICE, json!({ "url": "https://crates.io/" }))
+        .await;
+    assert_ne!(other, id);
+}
+
+#[tokio::test]
+async fn unknown_ids_return_404_with_the_error_body() {
+    let server = TestServer::start().await;
+    for path in ["/nope42", "/nope42+", "/api/links/nope42", "/no/such/route"] {
+        let res = server
+            .get(path)
+            .header("x-api-key", ALICE)
+            .header("x-request-id", "e2e-not-found")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND, "{}", path);
+        assert!(res.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("application/json"));
+        let (_, body) = json_body(res).await;
+        assert_error_body(&body, "not_found");
+        //客户端传入的 request id 原样返回
+        assert_eq!(body["request_id"], "e2e-not-found");
+    }
+}
+
+#[tokio::test]
+async fn invalid_requests_return_the_error_body() {
+    let server = TestServer::start().await;
+    server
+        .shorten_id(
+            ALICE,
+            json!({ "url": "https://www.rust-lang.org/", "alias": "taken" }),
+        )
+        .await;
+    let cases = [
+        (
+            ALICE,
+            json!({ "url": "not a url" }),
+            StatusCode::BAD_REQUEST,
+            "invalid_url",
+        ),
+        (
+            ALICE,
+            json!({ "url": "ftp://example.com/" }),
+            StatusCode::BAD_REQUEST,
+            "invalid_url",
+        ),
+        (
+            ALICE,
+            json!({ "link": "https://example.com/" }),
+            StatusCode::BAD_REQUEST,
+            "bad_request",
+        ),
+        (
+            ALICE,
+            json!({ "url": "https://example.com/", "ttl": 0 }),
+            StatusCode::BAD_REQUEST,
+            "bad_request",
+        ),
+        (
+            BOB,
+            json!({ "url": "https://example.com/", "alias": "taken" }),
+            StatusCode::CONFLICT,
+            "conflict",
+        ),
+        (
+            "wrong-key",
+            json!({ "url": "https://example.com/" }),
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+        ),
+    ];
+    for (key, request, status, code) in cases {
+        let (got, body) = server.shorten(key, request.clone()).await;
+        assert_eq!(got, status, "{} {}", request, body);
+        assert_error_body(&body, code);
+    }
+
+    //请求体不是 JSON
+    let res = server
+        .post("/", ALICE)
+        .header(CONTENT_TYPE, "application/json")
+        .body("{not json")
+        .send()
+        .await
+        .unwrap();
+    let (status, body) = json_body(res).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_error_body(&body, "bad_request");
+}
+
+#[tokio::test]
+async fn interstitial_pages_link_to_the_url_with_query_params() {
+    let server = TestServer::start().await;
+    let id = server
+        .shorten_id(
+            ALICE,
+            json!({
+                "url": "https://www.rust-lang.org/learn?lang=en",
+                "interstitial": true,
+                "query_params": { "utm_source": "newsletter" },
+            }),
+        )
+        .await;
+    let res = server.get(&format!("/{}", id)).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get(LOCATION).is_none());
+    let page = res.text().await.unwrap();
+    assert!(
+        page.contains("https://www.rust-lang.org/learn?lang=en&amp;utm_source=newsletter"),
+        "{}",
+        page
+    );
+}
+
+#[tokio::test]
+async fn warning_pages_do_not_count_clicks() {
+    let server = TestServer::start().await;
+    let id = server
+        .shorten_id(
+            ALICE,
+            json!({
+                "url": "https://www.rust-lang.org/",
+                "interstitial": true,
+                "max_clicks": 1,
+            }),
+        )
+        .await;
+    //没有跳转，不会用完 max_clicks
+    for _ in 0..2 {
+        let res = server.get(&format!("/{}", id)).send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+    let (status, link) = json_body(
+        server
+            .get(&format!("/api/links/{}", id))
+            .header("x-api-key", ALICE)
+            .send()
+            .await
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(link["clicks"], 0);
+}
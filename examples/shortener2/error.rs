@@ -0,0 +1,222 @@
+use axum::{
+    extract::{
+        rejection::{FormRejection, JsonRejection, PathRejection, QueryRejection},
+        FromRequest, FromRequestParts, Request,
+    },
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use nanoid::nanoid;
+use serde::Serialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tracing::{error, warn};
+use utoipa::ToSchema;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+tokio::task_local! {
+    //当前请求的 id，错误响应中带上它方便和日志对应
+    static REQUEST_ID: String;
+}
+
+//定义this error,
+#[derive(Error, Debug)]
+pub enum MyError {
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("retries limit error: {0}")]
+    RetriesLimit(String),
+    #[error("URL not found: {0}")]
+    UrlNotFound(String),
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("config error: {0}")]
+    Config(String),
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("migrate error: {0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+    #[error("internal error: {0}")]
+    Internal(String),
+    #[error("url is blocked: {0}")]
+    Blocked(String),
+    #[error("link is gone: {0}")]
+    Gone(String),
+    #[error("link unavailable for legal reasons: {0}")]
+    UnavailableForLegalReasons(String),
+    //需要等待的秒数
+    #[error("rate limit exceeded, retry after {0}s")]
+    RateLimited(u64),
+    #[error("service unavailable: {0}")]
+    Unavailable(String),
+}
+
+//所有接口统一的错误响应体
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    //稳定的错误码，例如 not_found、invalid_url、rate_limited
+    #[schema(example = "not_found")]
+    pub code: &'static str,
+    pub message: String,
+    pub request_id: Option<String>,
+    //错误的结构化补充信息，例如 rate_limited 的 {"retry_after": 秒数}，没有时为 null
+    #[schema(value_type = Option<Object>, example = json!({"retry_after": 30}))]
+    pub details: Option<Value>,
+}
+
+impl MyError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            MyError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            MyError::RetriesLimit(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            MyError::UrlNotFound(_) => StatusCode::NOT_FOUND,
+            MyError::InvalidUrl(_) => StatusCode::BAD_REQUEST,
+            MyError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            MyError::Forbidden(_) => StatusCode::FORBIDDEN,
+            MyError::Conflict(_) => StatusCode::CONFLICT,
+            MyError::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            MyError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            MyError::Migrate(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            MyError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            MyError::Blocked(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            MyError::Gone(_) => StatusCode::GONE,
+            MyError::UnavailableForLegalReasons(_) => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+            MyError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            MyError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    //稳定的错误码，客户端应该根据 code 而不是 message 判断错误类型
+    pub fn code(&self) -> &'static str {
+        match self {
+            MyError::Database(_)
+            | MyError::Config(_)
+            | MyError::Migrate(_)
+            | MyError::Internal(_) => "internal_error",
+            MyError::RetriesLimit(_) => "id_allocation_failed",
+            MyError::UrlNotFound(_) => "not_found",
+            MyError::InvalidUrl(_) => "invalid_url",
+            MyError::Unauthorized(_) => "unauthorized",
+            MyError::Forbidden(_) => "forbidden",
+            MyError::Conflict(_) => "conflict",
+            MyError::BadRequest(_) => "bad_request",
+            MyError::Blocked(_) => "blocked_url",
+            MyError::Gone(_) => "gone",
+            MyError::UnavailableForLegalReasons(_) => "unavailable_for_legal_reasons",
+            MyError::RateLimited(_) => "rate_limited",
+            MyError::Unavailable(_) => "service_unavailable",
+        }
+    }
+
+    //返回给客户端的描述，内部错误只记录日志，不把数据库等内部错误信息暴露出去
+    pub fn message(&self) -> String {
+        match self.status_code() {
+            StatusCode::INTERNAL_SERVER_ERROR => "internal server error".to_string(),
+            _ => self.to_string(),
+        }
+    }
+
+    //客户端可以直接使用的结构化信息，不需要从 message 中解析
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            MyError::RateLimited(secs) => Some(json!({ "retry_after": secs })),
+            _ => None,
+        }
+    }
+}
+
+impl IntoResponse for MyError {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        let request_id = REQUEST_ID.try_with(Clone::clone).ok();
+        if status.is_server_error() {
+            error!(request_id, "{}", self);
+        } else {
+            warn!(request_id, "{}", self);
+        }
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            request_id,
+            details: self.details(),
+        };
+        let mut res = (status, Json(body)).into_response();
+        if let MyError::RateLimited(secs) = self {
+            res.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(secs));
+        }
+        res
+    }
+}
+
+impl From<JsonRejection> for MyError {
+    fn from(e: JsonRejection) -> Self {
+        MyError::BadRequest(e.body_text())
+    }
+}
+
+impl From<FormRejection> for MyError {
+    fn from(e: FormRejection) -> Self {
+        MyError::BadRequest(e.body_text())
+    }
+}
+
+impl From<QueryRejection> for MyError {
+    fn from(e: QueryRejection) -> Self {
+        MyError::BadRequest(e.body_text())
+    }
+}
+
+impl From<PathRejection> for MyError {
+    fn from(e: PathRejection) -> Self {
+        MyError::BadRequest(e.body_text())
+    }
+}
+
+//和 axum 自带的提取器一样，只是解析失败时返回统一的错误响应体
+#[derive(Debug, FromRequest)]
+#[from_request(via(axum::Json), rejection(MyError))]
+pub struct AppJson<T>(pub T);
+
+#[derive(Debug, FromRequest)]
+#[from_request(via(axum::Form), rejection(MyError))]
+pub struct AppForm<T>(pub T);
+
+#[derive(Debug, FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(MyError))]
+pub struct AppQuery<T>(pub T);
+
+#[derive(Debug, FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(MyError))]
+pub struct AppPath<T>(pub T);
+
+//为每个请求分配 request id，客户端传了 x-request-id 时沿用客户端的
+pub async fn request_id(req: Request, next: Next) -> Response {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= MAX_REQUEST_ID_LEN)
+        .map(String::from)
+        .unwrap_or_else(|| nanoid!(16));
+    let mut res = REQUEST_ID.scope(id.clone(), next.run(req)).await;
+    if let Ok(v) = HeaderValue::from_str(&id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, v);
+    }
+    res
+}
+
+//未匹配到任何路由
+pub async fn not_found(req: Request) -> MyError {
+    MyError::UrlNotFound(req.uri().path().to_string())
+}
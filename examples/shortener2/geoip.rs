@@ -0,0 +1,156 @@
+use std::{fs, net::IpAddr, path::PathBuf};
+
+use serde::Deserialize;
+use tracing::info;
+
+use crate::MyError;
+
+//IP 段到国家代码的本地数据库，path 为空时不启用，规则中的 countries 条件都不会匹配
+//文件为 CSV，每行 "起始地址,结束地址,国家代码"，# 开头为注释，IPv4 和 IPv6 可以混在一起：
+//  203.0.113.0,203.0.113.255,AU
+//  2001:db8::,2001:db8::ffff,DE
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GeoIpConfig {
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+pub struct GeoIp {
+    //按起始地址排序且互不重叠，IPv4 转换为 IPv4-mapped IPv6 地址
+    ranges: Vec<(u128, u128, String)>,
+}
+
+impl GeoIp {
+    pub fn load(config: &GeoIpConfig) -> Result<Self, MyError> {
+        let Some(path) = &config.path else {
+            return Ok(Self::default());
+        };
+        let content = fs::read_to_string(path).map_err(|e| {
+            MyError::Config(format!("failed to read geoip {}: {}", path.display(), e))
+        })?;
+        let geoip = Self::parse(&content)
+            .map_err(|e| MyError::Config(format!("invalid geoip {}: {}", path.display(), e)))?;
+        info!(
+            "loaded {} geoip ranges from {}",
+            geoip.ranges.len(),
+            path.display()
+        );
+        Ok(geoip)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut ranges = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| format!("line {}: {} ({})", i + 1, reason, line);
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [start, end, country] = fields[..] else {
+                return Err(invalid("expected start,end,country"));
+            };
+            let start: IpAddr = start
+                .parse()
+                .map_err(|_| invalid("invalid start address"))?;
+            let end: IpAddr = end.parse().map_err(|_| invalid("invalid end address"))?;
+            if start.is_ipv4() != end.is_ipv4() || key(start) > key(end) {
+                return Err(invalid("invalid range"));
+            }
+            let country = normalize_country(country).ok_or_else(|| invalid("invalid country"))?;
+            ranges.push((key(start), key(end), country));
+        }
+        ranges.sort_by_key(|r| r.0);
+        if let Some(w) = ranges.windows(2).find(|w| w[1].0 <= w[0].1) {
+            return Err(format!("overlapping ranges for {} and {}", w[0].2, w[1].2));
+        }
+        Ok(Self { ranges })
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<&str> {
+        let ip = key(ip);
+        //最后一个起始地址不大于 ip 的段
+        let i = self.ranges.partition_point(|r| r.0 <= ip);
+        let (_, end, country) = self.ranges.get(i.checked_sub(1)?)?;
+        (ip <= *end).then_some(country.as_str())
+    }
+}
+
+fn key(ip: IpAddr) -> u128 {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().into(),
+        IpAddr::V6(ip) => ip.into(),
+    }
+}
+
+//两个字母的 ISO 3166-1 国家代码，转换为大写
+pub fn normalize_country(country: &str) -> Option<String> {
+    let country = country.trim();
+    (country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic()))
+        .then(|| country.to_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(geoip: &GeoIp, ip: &str) -> Option<String> {
+        geoip.lookup(ip.parse().unwrap()).map(String::from)
+    }
+
+    #[test]
+    fn ranges_are_looked_up_in_any_order() {
+        //文件中的顺序不影响查找
+        let geoip = GeoIp::parse(
+            "# start,end,country
+2001:db8::,2001:db8::ffff,de
+203.0.113.0, 203.0.113.255, AU
+
+198.51.100.0,198.51.100.127,JP
+198.51.100.128,198.51.100.128,US
+",
+        )
+        .unwrap();
+        for (ip, expected) in [
+            ("203.0.113.0", Some("AU")),
+            ("203.0.113.255", Some("AU")),
+            ("203.0.114.0", None),
+            ("198.51.100.127", Some("JP")),
+            ("198.51.100.128", Some("US")),
+            ("198.51.100.129", None),
+            ("10.0.0.1", None),
+            ("2001:db8::1", Some("DE")),
+            ("2001:db8::1:0", None),
+            //IPv4-mapped IPv6 地址按 IPv4 查找
+            ("::ffff:203.0.113.7", Some("AU")),
+        ] {
+            assert_eq!(lookup(&geoip, ip).as_deref(), expected, "{}", ip);
+        }
+        assert_eq!(lookup(&GeoIp::default(), "203.0.113.1"), None);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        for content in [
+            "203.0.113.0,203.0.113.255",
+            "203.0.113.0,203.0.113.255,AU,extra",
+            "203.0.113.x,203.0.113.255,AU",
+            "203.0.113.255,203.0.113.0,AU",
+            "203.0.113.0,2001:db8::,AU",
+            "203.0.113.0,203.0.113.255,AUS",
+            //重叠的范围，顺序打乱后也能发现
+            "203.0.113.128,203.0.113.255,US\n203.0.113.0,203.0.113.128,AU",
+            "2001:db8::,2001:db8::ffff,DE\n2001:db8::ff,2001:db8::1:0,FR",
+        ] {
+            assert!(GeoIp::parse(content).is_err(), "{}", content);
+        }
+    }
+
+    #[test]
+    fn countries_are_normalized() {
+        assert_eq!(normalize_country(" cn ").as_deref(), Some("CN"));
+        assert_eq!(normalize_country("C1"), None);
+        assert_eq!(normalize_country(""), None);
+    }
+}
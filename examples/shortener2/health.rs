@@ -0,0 +1,79 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{extract::State, response::IntoResponse, Json};
+use serde::Serialize;
+use tokio::signal;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+use crate::{DbState, MyError};
+
+//就绪检查中数据库查询的超时时间，超时视为数据库不可用
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthStatus {
+    #[schema(example = "ok")]
+    status: &'static str,
+}
+
+//GET /healthz 进程存活即返回 200，不检查依赖
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "ops",
+    responses((status = 200, description = "process is up", body = HealthStatus))
+)]
+pub async fn healthz() -> impl IntoResponse {
+    Json(HealthStatus { status: "ok" })
+}
+
+//GET /readyz 数据库可以正常查询时返回 200，否则返回 503
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "ops",
+    responses(
+        (status = 200, description = "ready to serve traffic", body = HealthStatus),
+        (status = 503, description = "database is unreachable", body = ErrorBody),
+    )
+)]
+pub async fn readyz(State(state): State<Arc<DbState>>) -> Result<impl IntoResponse, MyError> {
+    match tokio::time::timeout(READY_TIMEOUT, state.store.ping()).await {
+        Ok(Ok(())) => Ok(Json(HealthStatus { status: "ready" })),
+        Ok(Err(e)) => {
+            error!("readiness check failed: {}", e);
+            Err(MyError::Unavailable("database is unreachable".to_string()))
+        }
+        Err(_) => Err(MyError::Unavailable("database check timed out".to_string())),
+    }
+}
+
+//收到 SIGTERM 或 Ctrl-C 后返回，服务停止接受新连接并等待处理中的请求完成
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = signal::ctrl_c().await {
+            error!("failed to listen for ctrl-c: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(e) => {
+                error!("failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("received ctrl-c, shutting down"),
+        _ = terminate => info!("received SIGTERM, shutting down"),
+    }
+}
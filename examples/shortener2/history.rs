@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use axum::{extract::State, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    api::ListQuery,
+    auth::AuthUser,
+    error::{AppJson, AppPath, AppQuery},
+    host::Host,
+    store::Revision,
+    DbState, MyError,
+};
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RevisionList {
+    revisions: Vec<Revision>,
+    limit: i64,
+    offset: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RollbackRequest {
+    //要撤销的修订 id，链接恢复到这次修订之前的 url
+    revision: i64,
+}
+
+//GET /api/links/:id/history 按时间倒序列出目标 url 的修改记录
+#[utoipa::path(
+    get,
+    path = "/api/links/{id}/history",
+    tag = "api",
+    params(("id" = String, Path, description = "short link id"), ListQuery),
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "revisions, newest first", body = RevisionList),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+        (status = 403, description = "link is owned by someone else", body = ErrorBody),
+        (status = 404, description = "short link not found", body = ErrorBody),
+    )
+)]
+pub async fn list_history(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    user: AuthUser,
+    AppPath(id): AppPath<String>,
+    AppQuery(query): AppQuery<ListQuery>,
+) -> Result<impl IntoResponse, MyError> {
+    state.check_owner(&host.name, &id, &user.owner).await?;
+    let (limit, offset) = query.page();
+    let revisions = state
+        .store
+        .list_revisions(&host.name, &id, limit, offset)
+        .await?;
+    Ok(Json(RevisionList {
+        revisions,
+        limit,
+        offset,
+    }))
+}
+
+//POST /api/links/:id/rollback 恢复到某次修订之前的 url，回滚本身也记录为一条新的修订
+//恢复的 url 同样需要通过当前的屏蔽列表检查
+#[utoipa::path(
+    post,
+    path = "/api/links/{id}/rollback",
+    tag = "api",
+    params(("id" = String, Path, description = "short link id")),
+    request_body = RollbackRequest,
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "link pointing at the restored url", body = LinkRecord),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+        (status = 403, description = "link is owned by someone else", body = ErrorBody),
+        (status = 404, description = "short link or revision not found", body = ErrorBody),
+        (status = 409, description = "the old url is now used by another link", body = ErrorBody),
+        (status = 422, description = "the old url is now on the blocklist", body = ErrorBody),
+    )
+)]
+pub async fn rollback(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    user: AuthUser,
+    AppPath(id): AppPath<String>,
+    AppJson(payload): AppJson<RollbackRequest>,
+) -> Result<impl IntoResponse, MyError> {
+    state.check_owner(&host.name, &id, &user.owner).await?;
+    let revision = state
+        .store
+        .get_revision(&host.name, &id, payload.revision)
+        .await?
+        .ok_or_else(|| MyError::UrlNotFound(format!("{} revision {}", id, payload.revision)))?;
+    let url = state.normalize_url(&revision.old_url)?;
+    let link = state
+        .update_link(&host.name, &id, &user.owner, &url)
+        .await?;
+    Ok(Json(link))
+}
@@ -0,0 +1,219 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{async_trait, extract::FromRequestParts};
+use http::{header::HOST, request::Parts};
+use serde::Deserialize;
+
+use crate::{config::AppConfig, DbState, MyError};
+
+//一个品牌域名的配置，每个域名有独立的 id 空间，同一个 id 在不同域名下是不同的短链接
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostConfig {
+    //Host 头中的域名，可以带端口，例如 go.example.com
+    pub host: String,
+    //该域名下短链接对外的访问地址，默认为 https://<host>
+    #[serde(default)]
+    pub base_url: Option<String>,
+    //id 不存在时跳转到这里，不设置时返回 404
+    #[serde(default)]
+    pub default_redirect: Option<String>,
+}
+
+//请求所属的域名，由 Host 头决定，未配置的 Host 都属于默认域名
+#[derive(Debug, Clone, Default)]
+pub struct Host {
+    //数据库中的 domain 列，默认域名为空字符串
+    pub name: String,
+    pub base_url: String,
+    pub default_redirect: Option<String>,
+}
+
+impl Host {
+    //对外返回的短链接地址
+    pub fn short_url(&self, id: &str) -> String {
+        format!("{}/{}", self.base_url, id)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Hosts {
+    default: Host,
+    hosts: HashMap<String, Host>,
+}
+
+impl Hosts {
+    //配置已经校验过，host 为小写且不重复
+    pub fn new(config: &AppConfig) -> Self {
+        let default = Host {
+            name: String::new(),
+            base_url: config.base_url.clone(),
+            default_redirect: None,
+        };
+        let hosts = config
+            .hosts
+            .iter()
+            .map(|h| {
+                let host = Host {
+                    name: h.host.clone(),
+                    base_url: h
+                        .base_url
+                        .clone()
+                        .unwrap_or_else(|| format!("https://{}", h.host)),
+                    default_redirect: h.default_redirect.clone(),
+                };
+                (h.host.clone(), host)
+            })
+            .collect();
+        Self { default, hosts }
+    }
+
+    //先按完整的 Host 匹配，再去掉端口匹配，都没有时使用默认域名
+    pub fn resolve(&self, host: Option<&str>) -> Host {
+        let Some(host) = host.map(|h| h.trim().trim_end_matches('.').to_lowercase()) else {
+            return self.default.clone();
+        };
+        let without_port = host.rsplit_once(':').map(|(h, _)| h);
+        self.hosts
+            .get(&host)
+            .or_else(|| without_port.and_then(|h| self.hosts.get(h)))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+
+    //按数据库中的 domain 列查找，配置中已经删除的域名使用默认域名
+    pub fn get(&self, domain: &str) -> Host {
+        self.hosts.get(domain).unwrap_or(&self.default).clone()
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<DbState>> for Host {
+    type Rejection = MyError;
+
+    //HTTP/2 请求没有 Host 头，使用 :authority
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<DbState>,
+    ) -> Result<Self, Self::Rejection> {
+        let host = parts
+            .headers
+            .get(HOST)
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| parts.uri.authority().map(|a| a.as_str()));
+        Ok(state.hosts.resolve(host))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Request, StatusCode};
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, send, TestResponse};
+
+    const BRAND: &str = "go.example.com";
+    const BRAND_HOME: &str = "https://example.com/";
+
+    fn state() -> Arc<DbState> {
+        let mut config = testing::config();
+        config.hosts = vec![HostConfig {
+            host: BRAND.to_string(),
+            base_url: None,
+            default_redirect: Some(BRAND_HOME.to_string()),
+        }];
+        testing::state(config)
+    }
+
+    async fn get(state: &Arc<DbState>, host: &str, path: &str) -> TestResponse {
+        send(state, Request::get(path).header(HOST, host), None).await
+    }
+
+    async fn location(
+        state: &Arc<DbState>,
+        host: &str,
+        path: &str,
+    ) -> (StatusCode, Option<String>) {
+        let res = get(state, host, path).await;
+        (res.status, res.location().map(String::from))
+    }
+
+    #[tokio::test]
+    async fn links_are_resolved_by_host_and_id() {
+        let state = state();
+        let res = send(
+            &state,
+            Request::post("/").header(HOST, BRAND),
+            Some(json!({ "url": "https://www.rust-lang.org/" })),
+        )
+        .await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let body = res.json();
+        let id = body["url"]
+            .as_str()
+            .unwrap()
+            .strip_prefix("https://go.example.com/")
+            .expect("short url uses the host base url");
+
+        let path = format!("/{}", id);
+        let found = (
+            StatusCode::FOUND,
+            Some("https://www.rust-lang.org/".to_string()),
+        );
+        assert_eq!(location(&state, BRAND, &path).await, found);
+        //Host 中的端口和大小写不影响匹配
+        assert_eq!(location(&state, "GO.example.com:8080", &path).await, found);
+        //其他域名下没有这个 id
+        let res = get(&state, "localhost:9876", &path).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        assert_eq!(res.json()["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn each_host_has_its_own_id_namespace() {
+        let state = state();
+        for (host, url) in [
+            (BRAND, "https://www.rust-lang.org/"),
+            ("localhost:9876", "https://crates.io/"),
+        ] {
+            let res = send(
+                &state,
+                Request::post("/api/import?ids=preserve").header(HOST, host),
+                Some(json!([{ "id": "promo", "url": url }])),
+            )
+            .await;
+            assert_eq!(res.status, StatusCode::OK);
+            assert_eq!(res.json()["succeeded"], 1, "{}", res.body);
+        }
+        assert_eq!(
+            location(&state, BRAND, "/promo").await.1.as_deref(),
+            Some("https://www.rust-lang.org/")
+        );
+        assert_eq!(
+            location(&state, "localhost:9876", "/promo")
+                .await
+                .1
+                .as_deref(),
+            Some("https://crates.io/")
+        );
+
+        //api 也只能看到当前域名下的短链接
+        let body = get(&state, BRAND, "/api/links").await.json();
+        let links = body["links"].as_array().unwrap();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0]["domain"], BRAND);
+    }
+
+    #[tokio::test]
+    async fn unknown_ids_use_the_default_redirect_of_the_host() {
+        let state = state();
+        assert_eq!(
+            location(&state, BRAND, "/missing").await,
+            (StatusCode::FOUND, Some(BRAND_HOME.to_string()))
+        );
+        //默认域名没有配置默认跳转
+        let res = get(&state, "localhost:9876", "/missing").await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+}
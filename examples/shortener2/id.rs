@@ -0,0 +1,327 @@
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use nanoid::nanoid;
+use serde::Deserialize;
+
+use crate::MyError;
+
+pub const MAX_ID_LEN: usize = 32;
+//与固定路由同名的 id 无法访问，不能作为自定义 id 或者导入的 id
+const RESERVED_IDS: [&str; 5] = ["api", "batch", "docs", "healthz", "readyz"];
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+//随机 id 连续冲突这么多次后，认为当前长度的 id 空间已经拥挤，永久增加一位
+const GROW_AFTER_CONFLICTS: u32 = 2;
+
+//id 生成策略，通过配置 id_strategy 选择
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdStrategy {
+    //随机 nanoid，冲突多了自动增加长度
+    #[default]
+    Nanoid,
+    //数据库序列号的 base62 编码，不会冲突
+    Sequence,
+    //url 的 blake3 哈希的 base62 前缀，被占用时改用随机 id
+    Hash,
+}
+
+impl FromStr for IdStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nanoid" => Ok(Self::Nanoid),
+            "sequence" => Ok(Self::Sequence),
+            "hash" => Ok(Self::Hash),
+            _ => Err(format!(
+                "unknown id strategy {}, expected nanoid, sequence or hash",
+                s
+            )),
+        }
+    }
+}
+
+pub trait IdGenerator: Send + Sync {
+    //生成第 attempt 次（从 0 开始）尝试的候选 id，seq 只在 uses_sequence 返回 true 时有意义
+    fn generate(&self, url: &str, seq: u64, attempt: u32) -> String;
+
+    fn uses_sequence(&self) -> bool {
+        false
+    }
+}
+
+//导入时保留的 id 也要符合生成的 id 的字符集，+ 等字符在路由中有特殊含义
+pub fn check_id(id: &str) -> Result<(), MyError> {
+    let valid = (1..=MAX_ID_LEN).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if !valid {
+        return Err(MyError::BadRequest(format!(
+            "invalid id {:?}: expected 1 to {} characters of [A-Za-z0-9_-]",
+            id, MAX_ID_LEN
+        )));
+    }
+    if is_reserved(id) {
+        return Err(MyError::BadRequest(format!("id {:?} is reserved", id)));
+    }
+    Ok(())
+}
+
+//生成的 id 与固定路由同名时当作冲突处理，重新生成
+pub fn is_reserved(id: &str) -> bool {
+    RESERVED_IDS.contains(&id)
+}
+
+pub fn new_generator(strategy: IdStrategy, length: usize) -> Box<dyn IdGenerator> {
+    match strategy {
+        IdStrategy::Nanoid => Box::new(NanoidGenerator::new(length)),
+        IdStrategy::Sequence => Box::new(SequenceGenerator { min_length: length }),
+        IdStrategy::Hash => Box::new(HashGenerator { length }),
+    }
+}
+
+pub struct NanoidGenerator {
+    length: AtomicUsize,
+}
+
+impl NanoidGenerator {
+    pub fn new(length: usize) -> Self {
+        Self {
+            length: AtomicUsize::new(length),
+        }
+    }
+}
+
+impl IdGenerator for NanoidGenerator {
+    fn generate(&self, _url: &str, _seq: u64, attempt: u32) -> String {
+        let length = self.length.load(Ordering::Relaxed);
+        if attempt == GROW_AFTER_CONFLICTS && length < MAX_ID_LEN {
+            //并发时只有一个请求能把长度从 length 加到 length + 1
+            let _ = self.length.compare_exchange(
+                length,
+                length + 1,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        }
+        //本次请求的重试直接使用更长的 id
+        let length = (length + attempt as usize).min(MAX_ID_LEN);
+        nanoid!(length)
+    }
+}
+
+pub struct SequenceGenerator {
+    min_length: usize,
+}
+
+impl IdGenerator for SequenceGenerator {
+    //左侧用 '0' 补齐到 min_length，补齐后的编码仍然是一一对应的
+    fn generate(&self, _url: &str, seq: u64, _attempt: u32) -> String {
+        format!(
+            "{:0>width$}",
+            base62(&seq.to_be_bytes()),
+            width = self.min_length
+        )
+    }
+
+    fn uses_sequence(&self) -> bool {
+        true
+    }
+}
+
+pub struct HashGenerator {
+    length: usize,
+}
+
+impl IdGenerator for HashGenerator {
+    //同一个 url 第一次尝试总是得到相同的 id
+    //前缀被其他 url 或者同一 url 的其他非普通短链接占用时，重试使用逐次加长的随机 id，不会因为同一个 url 创建多次而用完重试次数
+    fn generate(&self, url: &str, _seq: u64, attempt: u32) -> String {
+        if attempt > 0 {
+            let length = (self.length + attempt as usize).min(MAX_ID_LEN);
+            return nanoid!(length);
+        }
+        let digest = base62(blake3::hash(url.as_bytes()).as_bytes());
+        format!("{:0>width$}", digest, width = MAX_ID_LEN)[..self.length].to_string()
+    }
+}
+
+//把大端字节串当作一个大整数做 base62 编码
+fn base62(bytes: &[u8]) -> String {
+    let mut digits: Vec<u8> = bytes.iter().copied().skip_while(|b| *b == 0).collect();
+    if digits.is_empty() {
+        return "0".to_string();
+    }
+    let mut out = Vec::new();
+    while !digits.is_empty() {
+        //长除法，每一轮得到最低位的余数
+        let mut rem = 0u32;
+        let mut quotient = Vec::with_capacity(digits.len());
+        for d in digits {
+            let acc = (rem << 8) | d as u32;
+            let q = (acc / 62) as u8;
+            rem = acc % 62;
+            if !(quotient.is_empty() && q == 0) {
+                quotient.push(q);
+            }
+        }
+        out.push(BASE62[rem as usize]);
+        digits = quotient;
+    }
+    out.reverse();
+    String::from_utf8(out).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{
+        config::AppConfig,
+        store::{MemoryStore, NewLink},
+        testing, DbState, MAX_ID_ATTEMPTS,
+    };
+
+    fn base62_u64(mut n: u64) -> String {
+        if n == 0 {
+            return "0".to_string();
+        }
+        let mut out = Vec::new();
+        while n > 0 {
+            out.push(BASE62[(n % 62) as usize]);
+            n /= 62;
+        }
+        out.reverse();
+        String::from_utf8(out).unwrap()
+    }
+
+    //用很短的 id 长度制造大量冲突，并发创建短链接后检查 id 唯一且都能解析回原 url
+    fn shorten_concurrently(strategy: IdStrategy, urls: HashSet<String>) {
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .build()
+            .unwrap();
+        rt.block_on(async move {
+            let config = AppConfig {
+                id_strategy: strategy,
+                id_length: 1,
+                ..AppConfig::default()
+            };
+            let state = testing::state(config);
+            let tasks: Vec<_> = urls
+                .iter()
+                .cloned()
+                .map(|url| {
+                    let state = state.clone();
+                    tokio::spawn(async move {
+                        let id = state.shorten(&NewLink::new(&url, "alice")).await.unwrap();
+                        (id, url)
+                    })
+                })
+                .collect();
+            let mut ids = HashSet::new();
+            for task in tasks {
+                let (id, url) = task.await.unwrap();
+                assert_eq!(state.get_url("", &id).await.unwrap(), url);
+                ids.insert(id);
+            }
+            assert_eq!(ids.len(), urls.len());
+        });
+    }
+
+    //第一次总是生成保留字，之后生成普通 id
+    struct ReservedFirst;
+
+    impl IdGenerator for ReservedFirst {
+        fn generate(&self, url: &str, _seq: u64, attempt: u32) -> String {
+            match attempt {
+                0 => "api".to_string(),
+                _ => format!("{}{}", url.len(), attempt),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn hash_ids_allow_many_links_to_the_same_url() {
+        let config = AppConfig {
+            id_strategy: IdStrategy::Hash,
+            ..AppConfig::default()
+        };
+        let state = testing::state(config);
+        let mut ids = HashSet::new();
+        for _ in 0..MAX_ID_ATTEMPTS * 2 {
+            let link = NewLink {
+                max_clicks: Some(1),
+                ..NewLink::new("https://example.com/", "alice")
+            };
+            ids.insert(state.shorten(&link).await.unwrap());
+        }
+        assert_eq!(ids.len(), MAX_ID_ATTEMPTS as usize * 2);
+    }
+
+    #[tokio::test]
+    async fn reserved_ids_are_regenerated() {
+        let mut state =
+            DbState::with_store(testing::config(), Arc::new(MemoryStore::default())).unwrap();
+        state.id_generator = Box::new(ReservedFirst);
+        let id = state
+            .shorten(&NewLink::new("https://example.com/", "alice"))
+            .await
+            .unwrap();
+        assert!(!is_reserved(&id), "{}", id);
+
+        let urls = vec!["https://example.com/a".to_string()];
+        let ids = state.shorten_many("", &urls, "alice").await.unwrap();
+        assert!(!is_reserved(&ids[&urls[0]]), "{:?}", ids);
+    }
+
+    proptest! {
+        #[test]
+        fn base62_matches_integer_encoding(n: u64) {
+            prop_assert_eq!(base62(&n.to_be_bytes()), base62_u64(n));
+        }
+
+        #[test]
+        fn sequence_ids_are_unique(a: u64, b: u64) {
+            let generator = SequenceGenerator { min_length: 6 };
+            let (id_a, id_b) = (generator.generate("", a, 0), generator.generate("", b, 0));
+            prop_assert!(id_a.len() >= 6);
+            prop_assert_eq!(a == b, id_a == id_b);
+        }
+
+        #[test]
+        fn hash_ids_are_stable_until_they_collide(url in "https://[a-z]{1,20}\\.com/[a-z0-9/]{0,30}", attempt in 1u32..8) {
+            let generator = HashGenerator { length: 6 };
+            let first = generator.generate(&url, 0, 0);
+            prop_assert_eq!(generator.generate(&url, 0, 0), first.as_str());
+            prop_assert_eq!(first.len(), 6);
+            prop_assert_eq!(generator.generate(&url, 0, attempt).len(), 6 + attempt as usize);
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        #[test]
+        fn nanoid_ids_are_unique_under_concurrency(urls in prop::collection::hash_set("https://[a-z]{1,12}\\.com/[a-z0-9]{0,8}", 100..300)) {
+            shorten_concurrently(IdStrategy::Nanoid, urls);
+        }
+
+        #[test]
+        fn sequence_ids_are_unique_under_concurrency(urls in prop::collection::hash_set("https://[a-z]{1,12}\\.com/[a-z0-9]{0,8}", 100..300)) {
+            shorten_concurrently(IdStrategy::Sequence, urls);
+        }
+
+        #[test]
+        fn hash_ids_are_unique_under_concurrency(urls in prop::collection::hash_set("https://[a-z]{1,12}\\.com/[a-z0-9]{0,8}", 100..300)) {
+            shorten_concurrently(IdStrategy::Hash, urls);
+        }
+    }
+}
@@ -0,0 +1,769 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use abuse::{check_enabled, disable_link, enable_link, list_reports, report_link};
+use anyhow::Result;
+use api::{delete_link, get_link, list_links, update_link};
+use auth::{ApiKeys, AuthUser};
+use axum::{
+    extract::{DefaultBodyLimit, State},
+    http::StatusCode,
+    middleware::{self, from_fn_with_state},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use batch::{shorten_batch, MAX_BATCH_BODY};
+use blocklist::Blocklist;
+use checker::Checker;
+use clap::{Parser, Subcommand};
+use config::AppConfig;
+use error::{not_found, request_id, AppJson, AppPath, MyError};
+use geoip::GeoIp;
+use health::{healthz, readyz, shutdown_signal};
+use history::{list_history, rollback};
+use host::{Host, Hosts};
+use http::{
+    header::{LOCATION, SET_COOKIE},
+    HeaderMap, HeaderValue,
+};
+use id::{check_id, is_reserved, new_generator, IdGenerator};
+use openapi::openapi_json;
+use preview::{interstitial_page, password_page, preview_page, unavailable_page};
+use protect::{hash_password, unlock};
+use qr::qr_code;
+use ratelimit::{limit_create, limit_redirect, MemoryRateLimitStore, RateLimitStore};
+use routing::{check_rules, Rule, Visitor};
+use serde::{Deserialize, Serialize};
+use split::{check_destinations, Destination};
+use store::{LinkFilter, LinkRecord, NewLink, Store};
+use transfer::{export_links, import_links};
+
+use tokio::net::TcpListener;
+use tracing::{info, level_filters::LevelFilter};
+use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
+use utoipa::ToSchema;
+use validate::{
+    check_query_params, check_redirect_status, merge_query, normalize_tags, normalize_text,
+    UrlPolicy, DEFAULT_REDIRECT_STATUS, MAX_DESCRIPTION_LEN, MAX_TITLE_LEN,
+};
+use webhook::{link_data, EventKind, Webhooks};
+
+mod abuse;
+mod api;
+mod auth;
+mod batch;
+mod blocklist;
+mod checker;
+mod config;
+#[cfg(test)]
+mod e2e;
+mod error;
+mod geoip;
+mod health;
+mod history;
+mod host;
+mod id;
+mod openapi;
+mod preview;
+mod protect;
+mod qr;
+mod ratelimit;
+mod routing;
+mod split;
+mod store;
+#[cfg(test)]
+mod testing;
+mod transfer;
+mod validate;
+mod webhook;
+
+//创建短链接时 id 冲突的最大尝试次数
+const MAX_ID_ATTEMPTS: u32 = 10;
+//ttl 最长一年
+const MAX_TTL_SECS: u64 = 365 * 24 * 3600;
+
+struct DbState {
+    store: Arc<dyn Store>,
+    id_generator: Box<dyn IdGenerator>,
+    config: AppConfig,
+    url_policy: UrlPolicy,
+    api_keys: ApiKeys,
+    blocklist: Arc<Blocklist>,
+    rate_limiter: Arc<dyn RateLimitStore>,
+    hosts: Hosts,
+    webhooks: Webhooks,
+    checker: Checker,
+    geoip: GeoIp,
+}
+
+impl DbState {
+    //按配置连接存储
+    async fn new(config: AppConfig) -> Result<Self, MyError> {
+        let blocklist = Arc::new(Blocklist::load(&config.blocklist)?);
+        let geoip = GeoIp::load(&config.geoip)?;
+        let store = store::connect(&config).await?;
+        Ok(Self {
+            blocklist,
+            geoip,
+            ..Self::with_store(config, store)?
+        })
+    }
+    fn with_store(config: AppConfig, store: Arc<dyn Store>) -> Result<Self, MyError> {
+        Ok(Self {
+            store,
+            id_generator: new_generator(config.id_strategy, config.id_length),
+            url_policy: config.url.clone(),
+            api_keys: ApiKeys::new(&config.api_keys),
+            blocklist: Arc::default(),
+            rate_limiter: Arc::new(MemoryRateLimitStore::default()),
+            hosts: Hosts::new(&config),
+            webhooks: Webhooks::new(config.webhooks.clone(), config.webhook_delivery.clone()),
+            checker: Checker::new(config.checker.clone())?,
+            geoip: GeoIp::default(),
+            config,
+        })
+    }
+    //校验请求中的 url 和可选配置，得到待写入 domain 域名下的短链接
+    fn new_link(
+        &self,
+        domain: &str,
+        url: &str,
+        owner: String,
+        options: LinkOptions,
+    ) -> Result<NewLink, MyError> {
+        let url = self.normalize_url(url)?;
+        let redirect_status = options.redirect_status.unwrap_or(DEFAULT_REDIRECT_STATUS);
+        check_redirect_status(redirect_status)?;
+        check_query_params(&options.query_params)?;
+        if options.max_clicks == Some(0) {
+            return Err(MyError::BadRequest(
+                "max_clicks must be greater than 0".to_string(),
+            ));
+        }
+        let password_hash = options.password.as_deref().map(hash_password).transpose()?;
+        let mut destinations = options.destinations;
+        if !destinations.is_empty() {
+            for d in destinations.iter_mut() {
+                d.url = self.normalize_url(&d.url)?;
+                d.clicks = 0;
+            }
+            check_destinations(&url, &destinations)?;
+        } else if options.sticky {
+            return Err(MyError::BadRequest(
+                "sticky requires destinations".to_string(),
+            ));
+        }
+        let mut rules = options.rules;
+        for rule in rules.iter_mut() {
+            rule.url = self.normalize_url(&rule.url)?;
+        }
+        check_rules(&mut rules)?;
+        let title = normalize_text("title", options.title, MAX_TITLE_LEN)?;
+        let description = normalize_text("description", options.description, MAX_DESCRIPTION_LEN)?;
+        let tags = normalize_tags(&options.tags)?;
+        let expires_at = match options.ttl {
+            Some(ttl) if !(1..=MAX_TTL_SECS).contains(&ttl) => {
+                return Err(MyError::BadRequest(format!(
+                    "ttl must be between 1 and {} seconds",
+                    MAX_TTL_SECS
+                )))
+            }
+            Some(ttl) => Some(chrono::Utc::now() + chrono::Duration::seconds(ttl as i64)),
+            None => None,
+        };
+        Ok(NewLink {
+            interstitial: options.interstitial,
+            redirect_status,
+            query_params: options.query_params,
+            password_hash,
+            max_clicks: options.max_clicks.map(i64::from),
+            destinations,
+            sticky: options.sticky,
+            title,
+            description,
+            tags,
+            expires_at,
+            rules,
+            domain: domain.to_string(),
+            ..NewLink::new(url, owner)
+        })
+    }
+    //校验并规范化 url，非法的 url 返回 400，命中屏蔽列表返回 422
+    fn normalize_url(&self, raw: &str) -> Result<String, MyError> {
+        let url = self.url_policy.normalize(raw)?;
+        self.blocklist.check(&url)?;
+        Ok(url)
+    }
+    async fn migrate(&self) -> Result<(), MyError> {
+        self.store.migrate().await
+    }
+    //为 count 个 url 准备生成 id 需要的序列号
+    async fn sequences(&self, count: usize) -> Result<Vec<u64>, MyError> {
+        match self.id_generator.uses_sequence() {
+            true => self.store.next_sequence(count).await,
+            false => Ok(vec![0; count]),
+        }
+    }
+    //创建短链接
+    async fn shorten(&self, link: &NewLink) -> Result<String, MyError> {
+        for attempt in 0..MAX_ID_ATTEMPTS {
+            let seq = self.sequences(1).await?[0];
+            let id = self.id_generator.generate(&link.url, seq, attempt);
+            if is_reserved(&id) {
+                info!(
+                    "生成的 id {} 是保留字，重试... 已尝试次数: {}",
+                    id,
+                    attempt + 1
+                );
+                continue;
+            }
+            match self.store.insert_link(&id, link).await? {
+                Some(ret) => {
+                    if ret.created {
+                        self.notify_created(&link.domain, std::slice::from_ref(&ret.id))
+                            .await;
+                    }
+                    return Ok(ret.id);
+                }
+                //主键冲突，重试
+                None => info!("主键冲突，重试... 已尝试次数: {}", attempt + 1),
+            }
+        }
+        //重试次数用尽
+        Err(MyError::RetriesLimit("主键冲突且重试次数用尽".to_string()))
+    }
+    //使用指定的 id 创建短链接，id 已被占用，或者 url 已经以其他 id 存在时返回 409
+    async fn shorten_as(&self, id: &str, link: &NewLink) -> Result<String, MyError> {
+        check_id(id)?;
+        match self.store.insert_link(id, link).await? {
+            Some(ret) if ret.id == id => {
+                if ret.created {
+                    self.notify_created(&link.domain, std::slice::from_ref(&ret.id))
+                        .await;
+                }
+                Ok(ret.id)
+            }
+            Some(existing) => Err(MyError::Conflict(format!(
+                "url already shortened as {}",
+                existing.id
+            ))),
+            None => Err(MyError::Conflict(format!("id already in use: {}", id))),
+        }
+    }
+    //批量创建短链接，返回 url -> id 的映射，urls 需要事先去重
+    async fn shorten_many(
+        &self,
+        domain: &str,
+        urls: &[String],
+        owner: &str,
+    ) -> Result<HashMap<String, String>, MyError> {
+        for attempt in 0..MAX_ID_ATTEMPTS {
+            let ids: Vec<String> = self
+                .sequences(urls.len())
+                .await?
+                .into_iter()
+                .zip(urls)
+                .map(|(seq, url)| self.id_generator.generate(url, seq, attempt))
+                .collect();
+            if let Some(id) = ids.iter().find(|id| is_reserved(id)) {
+                info!(
+                    "生成的 id {} 是保留字，重试... 已尝试次数: {}",
+                    id,
+                    attempt + 1
+                );
+                continue;
+            }
+            match self.store.insert_links(domain, &ids, urls, owner).await? {
+                Some(ret) => {
+                    let created: Vec<String> = ret
+                        .values()
+                        .filter(|r| r.created)
+                        .map(|r| r.id.clone())
+                        .collect();
+                    self.notify_created(domain, &created).await;
+                    return Ok(ret.into_iter().map(|(url, r)| (url, r.id)).collect());
+                }
+                //整批中任意一个 id 主键冲突都会让整批失败，整批重新生成 id 再试
+                None => info!("批量插入主键冲突，重试... 已尝试次数: {}", attempt + 1),
+            }
+        }
+        Err(MyError::RetriesLimit("主键冲突且重试次数用尽".to_string()))
+    }
+    //新建的短链接发送 link.created 事件，url 已存在时沿用的短链接不发送
+    async fn notify_created(&self, domain: &str, ids: &[String]) {
+        if !self.webhooks.wants(EventKind::Created) {
+            return;
+        }
+        let host = self.hosts.get(domain);
+        let mut deliveries = Vec::new();
+        for id in ids {
+            match self.store.get_link(domain, id).await {
+                Ok(Some(link)) => deliveries.extend(
+                    self.webhooks
+                        .deliveries(EventKind::Created, link_data(&link, &host)),
+                ),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("failed to load {} for webhooks: {}", id, e),
+            }
+        }
+        self.webhooks
+            .enqueue(self.store.as_ref(), &deliveries)
+            .await;
+    }
+    //跳转时发送 link.clicked 事件，这次点击用完 max_clicks 时再发送 link.expired 事件
+    async fn notify_clicked(&self, link: &LinkRecord, destination: &str) {
+        let clicked = self.webhooks.wants(EventKind::Clicked);
+        let exhausted = link.is_exhausted() && self.webhooks.wants(EventKind::Expired);
+        if !clicked && !exhausted {
+            return;
+        }
+        let data = link_data(link, &self.hosts.get(&link.domain));
+        let mut deliveries = Vec::new();
+        if clicked {
+            let mut data = data.clone();
+            data["destination"] = serde_json::json!(destination);
+            deliveries.extend(self.webhooks.deliveries(EventKind::Clicked, data));
+        }
+        if exhausted {
+            let mut data = data;
+            data["reason"] = serde_json::json!("max_clicks");
+            deliveries.extend(self.webhooks.deliveries(EventKind::Expired, data));
+        }
+        self.webhooks
+            .enqueue(self.store.as_ref(), &deliveries)
+            .await;
+    }
+    //获取短链接
+    async fn get_url(&self, domain: &str, id: &str) -> Result<String, MyError> {
+        Ok(self.get_link(domain, id).await?.url)
+    }
+    async fn get_link(&self, domain: &str, id: &str) -> Result<LinkRecord, MyError> {
+        self.store
+            .get_link(domain, id)
+            .await?
+            .ok_or_else(|| MyError::UrlNotFound(id.to_string()))
+    }
+    //跳转前检查短链接是否可用，不记录点击
+    //设置了密码且 unlocked 为 false 时返回 None，由调用方展示密码页
+    async fn find_link(
+        &self,
+        domain: &str,
+        id: &str,
+        unlocked: bool,
+    ) -> Result<Option<LinkRecord>, MyError> {
+        let link = self.get_link(domain, id).await?;
+        check_enabled(&link)?;
+        if link.is_exhausted() {
+            return Err(MyError::Gone(format!("{} reached its click limit", id)));
+        }
+        if link.is_expired() {
+            return Err(MyError::Gone(format!("{} has expired", id)));
+        }
+        if link.password_hash.is_some() && !unlocked {
+            return Ok(None);
+        }
+        Ok(Some(link))
+    }
+    //确定跳转时记录一次点击，返回计数后的记录
+    //检查之后链接被禁用、过期或者用完了点击次数时返回对应的错误
+    async fn count_click(
+        &self,
+        domain: &str,
+        id: &str,
+        unlocked: bool,
+    ) -> Result<Option<LinkRecord>, MyError> {
+        if let Some(link) = self.store.resolve_link(domain, id, unlocked).await? {
+            return Ok(Some(link));
+        }
+        //没有计数时再查一次，找出不能跳转的原因
+        if self.find_link(domain, id, unlocked).await?.is_none() {
+            return Ok(None);
+        }
+        //两次查询之间链接被重新启用，再尝试一次
+        self.store.resolve_link(domain, id, unlocked).await
+    }
+    async fn list_links(
+        &self,
+        domain: &str,
+        owner: &str,
+        filter: &LinkFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<LinkRecord>, MyError> {
+        self.store
+            .list_links(domain, owner, filter, limit, offset)
+            .await
+    }
+    //检查链接是否存在且属于 owner，返回链接
+    async fn check_owner(
+        &self,
+        domain: &str,
+        id: &str,
+        owner: &str,
+    ) -> Result<LinkRecord, MyError> {
+        match self.store.get_link(domain, id).await? {
+            None => Err(MyError::UrlNotFound(id.to_string())),
+            Some(link) if link.owner.as_deref() == Some(owner) => Ok(link),
+            Some(_) => Err(MyError::Forbidden(format!(
+                "link {} is not owned by you",
+                id
+            ))),
+        }
+    }
+    //删除短链接，只有归属人可以删除
+    async fn delete_link(&self, domain: &str, id: &str, owner: &str) -> Result<(), MyError> {
+        self.check_owner(domain, id, owner).await?;
+        self.store.delete_link(domain, id).await?;
+        Ok(())
+    }
+    //修改短链接指向的 url，只有归属人可以修改，每次修改都会记录修订
+    async fn update_link(
+        &self,
+        domain: &str,
+        id: &str,
+        owner: &str,
+        url: &str,
+    ) -> Result<LinkRecord, MyError> {
+        let link = self.check_owner(domain, id, owner).await?;
+        //多目标短链接的 url 必须是目标之一，不能单独修改
+        if !link.destinations.is_empty() {
+            return Err(MyError::BadRequest(
+                "url of a link with destinations cannot be changed".to_string(),
+            ));
+        }
+        self.store
+            .update_link(domain, id, url, owner)
+            .await?
+            .ok_or_else(|| MyError::UrlNotFound(id.to_string()))
+    }
+}
+
+//请求体，实现Deserialize，反序列化，Json中获取，所以需要反序列化
+#[derive(Debug, Deserialize, ToSchema)]
+struct UrlRequest {
+    url: String,
+    //自定义 id，不设置时按 id_strategy 生成
+    #[schema(example = "rust-book")]
+    alias: Option<String>,
+    //为 true 时在响应中附带 data uri 格式的二维码
+    #[serde(default)]
+    qr: bool,
+    #[serde(flatten)]
+    options: LinkOptions,
+}
+//创建短链接时除 url 以外的可选配置，导入接口中也使用
+#[derive(Debug, Default, Deserialize, ToSchema)]
+struct LinkOptions {
+    //为 true 时跳转前总是先展示警告页
+    #[serde(default)]
+    interstitial: bool,
+    //301、302（默认）、307 或 308
+    #[schema(example = 302)]
+    redirect_status: Option<u16>,
+    //跳转时合并到目标地址的参数，例如 {"utm_source": "newsletter"}
+    #[serde(default)]
+    query_params: BTreeMap<String, String>,
+    //设置后跳转前需要输入密码，只保存哈希
+    #[schema(write_only)]
+    password: Option<String>,
+    //最多可以跳转的次数，用完后返回 410
+    max_clicks: Option<u32>,
+    //A/B 测试：按权重分配到多个目标，url 需要是其中之一
+    #[serde(default)]
+    destinations: Vec<Destination>,
+    //为 true 时同一访客通过 cookie 固定跳转到同一个目标
+    #[serde(default)]
+    sticky: bool,
+    //标题和描述可以在 GET /api/links?q= 中搜索
+    #[schema(example = "Rust 官网")]
+    title: Option<String>,
+    description: Option<String>,
+    //不区分大小写，可以在 GET /api/links?tag= 中过滤
+    #[serde(default)]
+    #[schema(example = json!(["rust", "docs"]))]
+    tags: Vec<String>,
+    //多少秒后过期，过期后跳转返回 410
+    #[schema(example = 86400)]
+    ttl: Option<u64>,
+    //按访客的国家、设备或者语言跳转到其他目标，按 priority 从高到低匹配，都不匹配时跳转到 url
+    #[serde(default)]
+    rules: Vec<Rule>,
+}
+//响应体，实现Serialize，序列化，返回Json，所以需要序列化
+#[derive(Debug, Serialize, ToSchema)]
+struct UrlResponse {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    qr: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+#[command(about = "url shortener service")]
+struct Cli {
+    /// TOML 配置文件路径，环境变量 SHORTENER_* 会覆盖文件中的配置
+    #[arg(short, long, global = true, env = "SHORTENER_CONFIG")]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// 执行数据库迁移后启动服务（默认）
+    Serve {
+        /// 启动时不执行数据库迁移
+        #[arg(long)]
+        skip_migrations: bool,
+    },
+    /// 只执行数据库迁移
+    Migrate,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let layer = Layer::new().pretty().with_filter(LevelFilter::INFO);
+    tracing_subscriber::registry().with(layer).init();
+
+    let cli = Cli::parse();
+
+    let config = AppConfig::load(cli.config.as_deref())?;
+    let addr = config.listen_addr;
+    let state = Arc::new(DbState::new(config).await?);
+    match cli.command.unwrap_or(Command::Serve {
+        skip_migrations: false,
+    }) {
+        Command::Migrate => {
+            state.migrate().await?;
+            return Ok(());
+        }
+        Command::Serve { skip_migrations } => {
+            if !skip_migrations {
+                state.migrate().await?;
+            }
+        }
+    }
+    state.blocklist.clone().watch();
+    if state.webhooks.is_enabled() {
+        tokio::spawn(webhook::run(state.clone()));
+    }
+    if state.checker.is_enabled() {
+        tokio::spawn(checker::run(state.clone()));
+    }
+
+    let app = app(state.clone());
+
+    let listener = TcpListener::bind(addr).await?;
+    info!("listening on {}", addr);
+    //限流需要客户端地址
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
+    //处理中的请求都已完成，关闭数据库连接池
+    state.store.close().await;
+    info!("server stopped");
+    Ok(())
+}
+
+//所有路由，测试中也使用它构造服务
+fn app(state: Arc<DbState>) -> Router {
+    axum::Router::new()
+        .route(
+            "/",
+            post(shorten).layer(from_fn_with_state(state.clone(), limit_create)),
+        )
+        .route(
+            "/batch",
+            post(shorten_batch)
+                .layer(DefaultBodyLimit::max(MAX_BATCH_BODY))
+                .layer(from_fn_with_state(state.clone(), limit_create)),
+        )
+        .route(
+            "/:id",
+            get(redirect)
+                .post(unlock)
+                .layer(from_fn_with_state(state.clone(), limit_redirect)),
+        )
+        .route("/:id/qr", get(qr_code))
+        .route(
+            "/:id/report",
+            post(report_link).layer(from_fn_with_state(state.clone(), limit_create)),
+        )
+        .route("/api/links", get(list_links))
+        .route(
+            "/api/links/:id",
+            get(get_link).delete(delete_link).patch(update_link),
+        )
+        .route("/api/links/:id/history", get(list_history))
+        .route("/api/links/:id/rollback", post(rollback))
+        .route("/api/export", get(export_links))
+        .route(
+            "/api/import",
+            post(import_links).layer(DefaultBodyLimit::max(MAX_BATCH_BODY)),
+        )
+        .route("/api/admin/reports", get(list_reports))
+        .route(
+            "/api/admin/links/:id/disabled",
+            put(disable_link).delete(enable_link),
+        )
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/openapi.json", get(openapi_json))
+        .merge(openapi::docs())
+        .fallback(not_found)
+        .layer(middleware::from_fn(request_id))
+        .with_state(state)
+}
+
+#[utoipa::path(
+    post,
+    path = "/",
+    tag = "links",
+    request_body = UrlRequest,
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 201, description = "short link created, or the existing one for the same url (links with a password, max_clicks, destinations, title, tags or ttl are never shared)", body = UrlResponse),
+        (status = 400, description = "invalid url, alias or options", body = ErrorBody),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+        (status = 409, description = "alias is already in use, or the url is already shortened under another id", body = ErrorBody),
+        (status = 422, description = "url is on the blocklist", body = ErrorBody),
+        (status = 429, description = "rate limited, see Retry-After", body = ErrorBody),
+    )
+)]
+async fn shorten(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    user: AuthUser,
+    AppJson(payload): AppJson<UrlRequest>,
+) -> Result<impl IntoResponse, MyError> {
+    //先校验并规范化 url 和可选配置，非法时返回 400
+    let link = state.new_link(&host.name, &payload.url, user.owner, payload.options)?;
+    let id = match payload.alias {
+        Some(alias) => state.shorten_as(&alias, &link).await?,
+        None => state.shorten(&link).await?,
+    };
+    let url = host.short_url(&id);
+    let qr = match payload.qr {
+        true => Some(qr::data_uri(&url, &state.config.qr)?),
+        false => None,
+    };
+    let body = Json(UrlResponse { url, qr });
+    //返回201状态码，创建成功，返回是一个tuple，包含状态码和body，实现了IntoResponse
+    Ok((StatusCode::CREATED, body))
+}
+
+//GET /:id 跳转，GET /:id+ 展示预览页
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "links",
+    params(("id" = String, Path, description = "short link id, append + for the preview page")),
+    responses(
+        (status = 302, description = "redirect to the destination, the status is configured per link (301, 302, 307 or 308); links with several destinations pick one by weight; unknown ids go to the default_redirect of the host when it is set",
+            headers(
+                ("location" = String, description = "destination url"),
+                ("set-cookie" = String, description = "sticky links remember the chosen destination"),
+            )),
+        (status = 200, description = "preview page, interstitial warning page, password form, or the destination unavailable page when checker.unavailable_page is on and the link is dead", content_type = "text/html"),
+        (status = 404, description = "short link not found", body = ErrorBody),
+        (status = 410, description = "link disabled for abuse, expired or click limit reached", body = ErrorBody),
+        (status = 429, description = "rate limited, see Retry-After", body = ErrorBody),
+        (status = 451, description = "link disabled for legal reasons", body = ErrorBody),
+    )
+)]
+async fn redirect(
+    AppPath(id): AppPath<String>,
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    visitor: Visitor,
+    req_headers: HeaderMap,
+) -> Result<Response, MyError> {
+    //以 + 结尾时只展示预览页，不跳转
+    if let Some(id) = id.strip_suffix('+') {
+        let link = state.get_link(&host.name, id).await?;
+        check_enabled(&link)?;
+        if link.is_expired() {
+            return Err(MyError::Gone(format!("{} has expired", id)));
+        }
+        return Ok(Html(preview_page(&link, &host.short_url(id))).into_response());
+    }
+    let link = match state.find_link(&host.name, &id, false).await {
+        Ok(Some(link)) => link,
+        Ok(None) => return Ok(Html(password_page(None)).into_response()),
+        //域名配置了默认跳转地址时，不存在的 id 都跳转到这里
+        Err(MyError::UrlNotFound(_)) if host.default_redirect.is_some() => {
+            let target = host.default_redirect.as_deref().unwrap_or_default();
+            let location = HeaderValue::from_str(target).map_err(|e| {
+                MyError::Internal(format!("invalid default redirect for {}: {}", host.name, e))
+            })?;
+            return Ok((StatusCode::FOUND, [(LOCATION, location)]).into_response());
+        }
+        Err(e) => return Err(e),
+    };
+    let status = StatusCode::from_u16(link.redirect_status as u16).unwrap_or(StatusCode::FOUND);
+    redirect_to(&state, &link, &visitor, &req_headers, status, false).await
+}
+
+//跳转到短链接的目标地址，不受信任的目标先展示警告页
+//只有真正跳转时才记录点击，展示警告页不计数，也不消耗 max_clicks
+async fn redirect_to(
+    state: &DbState,
+    link: &LinkRecord,
+    visitor: &Visitor,
+    req_headers: &HeaderMap,
+    status: StatusCode,
+    unlocked: bool,
+) -> Result<Response, MyError> {
+    //先按规则匹配，都不匹配时多目标短链接选出本次的目标
+    let mut headers = HeaderMap::new();
+    let (target, index) = match routing::route(link, visitor) {
+        Some(url) => (url, None),
+        None => match split::choose(link, req_headers) {
+            Some((index, cookie)) => {
+                if let Some(cookie) = cookie {
+                    headers.insert(SET_COOKIE, cookie);
+                }
+                (link.destinations[index].url.as_str(), Some(index))
+            }
+            None => (link.url.as_str(), None),
+        },
+    };
+    //警告页中的地址也需要带上合并的参数
+    let url = merge_query(target, &link.query_params)?;
+    //只检查了 url，多目标短链接选中其他目标时照常跳转
+    let page = if target == link.url && state.checker.shows_unavailable(link) {
+        Some(unavailable_page(&url, link.check_status))
+    } else if link.interstitial || !state.config.domains.is_trusted(target) {
+        Some(interstitial_page(&url))
+    } else {
+        None
+    };
+    if let Some(page) = page {
+        return Ok((headers, Html(page)).into_response());
+    }
+    let counted = match state.count_click(&link.domain, &link.id, unlocked).await? {
+        Some(counted) => counted,
+        //检查之后链接被设置了密码
+        None => return Ok(Html(password_page(None)).into_response()),
+    };
+    if let Some(index) = index {
+        state
+            .store
+            .add_destination_click(&link.domain, &link.id, index)
+            .await?;
+    }
+    state.notify_clicked(&counted, target).await;
+    //返回是一个tuple，包含状态码和body，实现了IntoResponse
+    //headermap，包含Location头，值为url
+    let location = HeaderValue::from_str(&url)
+        .map_err(|e| MyError::Internal(format!("invalid location for {}: {}", link.id, e)))?;
+    headers.insert(LOCATION, location);
+    Ok((headers, status).into_response())
+}
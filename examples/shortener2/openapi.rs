@@ -0,0 +1,103 @@
+use axum::{response::IntoResponse, Json};
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+use utoipa_redoc::{Redoc, Servable};
+
+use crate::{
+    abuse, api, batch, error::ErrorBody, health, history, protect, qr, routing, split, store,
+    transfer,
+};
+
+//OpenAPI 3 文档由各个 handler 上的 #[utoipa::path] 和类型上的 ToSchema 生成，新增接口时需要加到 paths 中
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "shortener", description = "URL shortener HTTP API"),
+    paths(
+        crate::shorten,
+        crate::redirect,
+        protect::unlock,
+        batch::shorten_batch,
+        qr::qr_code,
+        abuse::report_link,
+        api::list_links,
+        api::get_link,
+        api::delete_link,
+        api::update_link,
+        history::list_history,
+        history::rollback,
+        transfer::export_links,
+        transfer::import_links,
+        abuse::list_reports,
+        abuse::disable_link,
+        abuse::enable_link,
+        health::healthz,
+        health::readyz,
+    ),
+    components(schemas(
+        crate::UrlRequest,
+        crate::LinkOptions,
+        crate::UrlResponse,
+        protect::UnlockForm,
+        ErrorBody,
+        store::LinkRecord,
+        split::Destination,
+        routing::Rule,
+        routing::Device,
+        store::Report,
+        store::Revision,
+        api::LinkList,
+        api::UpdateLinkRequest,
+        history::RevisionList,
+        history::RollbackRequest,
+        batch::BatchResponse,
+        batch::BatchItem,
+        batch::BatchError,
+        qr::QrFormat,
+        qr::EcLevel,
+        abuse::ReportRequest,
+        abuse::DisableRequest,
+        abuse::DisableReason,
+        abuse::ReportList,
+        transfer::Format,
+        transfer::IdMode,
+        health::HealthStatus,
+    )),
+    modifiers(&SecurityAddon),
+    tags(
+        (name = "links", description = "create short links and follow them"),
+        (name = "api", description = "manage your own links"),
+        (name = "abuse", description = "abuse reports"),
+        (name = "admin", description = "moderation, requires an admin api key"),
+        (name = "ops", description = "health checks for orchestrators"),
+    )
+)]
+pub struct ApiDoc;
+
+//api key 可以放在 Authorization: Bearer 或者 X-API-Key 中
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key"))),
+        );
+    }
+}
+
+//GET /openapi.json
+pub async fn openapi_json() -> impl IntoResponse {
+    Json(ApiDoc::openapi())
+}
+
+//GET /docs 文档页面，页面中的脚本从 CDN 加载
+pub fn docs() -> Redoc<utoipa::openapi::OpenApi> {
+    Redoc::with_url("/docs", ApiDoc::openapi())
+}
@@ -0,0 +1,201 @@
+use serde::Deserialize;
+use url::Url;
+
+use crate::store::LinkRecord;
+
+//目标域名的信任列表，deny 中的域名或者 allow 非空时不在 allow 中的域名都会先展示警告页
+//域名匹配包含子域名，例如 example.com 同时匹配 www.example.com
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DomainPolicy {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl DomainPolicy {
+    pub fn is_trusted(&self, url: &str) -> bool {
+        let Some(host) = Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_lowercase))
+        else {
+            return false;
+        };
+        if self.deny.iter().any(|d| domain_matches(&host, d)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|d| domain_matches(&host, d))
+    }
+}
+
+pub fn domain_matches(host: &str, domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.');
+    host.eq_ignore_ascii_case(domain)
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+//GET /:id+ 的预览页，不计入点击数，设置了密码的链接不展示目标地址
+pub fn preview_page(link: &LinkRecord, short_url: &str) -> String {
+    if link.password_hash.is_some() {
+        return protected_preview_page(short_url);
+    }
+    let targets = match link.destinations.is_empty() {
+        true => format!(
+            r#"<p><a href="{url}" rel="noopener noreferrer">{url}</a></p>"#,
+            url = escape(&link.url)
+        ),
+        //多目标短链接列出所有目标和各自的流量占比
+        false => {
+            let total: u32 = link.destinations.iter().map(|d| d.weight).sum();
+            let items: String = link
+                .destinations
+                .iter()
+                .map(|d| {
+                    format!(
+                        "<li><a href=\"{url}\" rel=\"noopener noreferrer\">{url}</a> ({share}%)</li>\n",
+                        url = escape(&d.url),
+                        share = d.weight * 100 / total.max(1),
+                    )
+                })
+                .collect();
+            format!("<ul>\n{}</ul>", items)
+        }
+    };
+    let rules: String = link
+        .rules
+        .iter()
+        .map(|r| {
+            let devices: Vec<&str> = r.devices.iter().map(|d| d.as_str()).collect();
+            let conditions: Vec<String> = [
+                ("countries", r.countries.join(", ")),
+                ("devices", devices.join(", ")),
+                ("languages", r.languages.join(", ")),
+            ]
+            .into_iter()
+            .filter(|(_, values)| !values.is_empty())
+            .map(|(name, values)| format!("{}: {}", name, escape(&values)))
+            .collect();
+            format!(
+                "<li>{conditions} &rarr; <a href=\"{url}\" rel=\"noopener noreferrer\">{url}</a></li>\n",
+                conditions = conditions.join("; "),
+                url = escape(&r.url),
+            )
+        })
+        .collect();
+    //有跳转规则时，列出按访客跳转的其他目标
+    let targets = match rules.is_empty() {
+        true => targets,
+        false => format!(
+            "{}\n<p>Some visitors go elsewhere:</p>\n<ul>\n{}</ul>",
+            targets, rules
+        ),
+    };
+    page(
+        "Link preview",
+        &format!(
+            r#"<p><code>{short}</code> redirects to:</p>
+{targets}
+<ul>
+<li>Created: {created}</li>
+<li>Clicks: {clicks}</li>
+</ul>"#,
+            short = escape(short_url),
+            targets = targets,
+            created = link.created_at.format("%Y-%m-%d %H:%M UTC"),
+            clicks = link.clicks,
+        ),
+    )
+}
+
+fn protected_preview_page(short_url: &str) -> String {
+    page(
+        "Link preview",
+        &format!(
+            r#"<p><code>{short}</code> is password protected.</p>
+<p><a href="{short}">Open the link</a> to enter the password.</p>"#,
+            short = escape(short_url),
+        ),
+    )
+}
+
+//设置了密码的链接先展示密码页，表单提交到 POST /:id
+pub fn password_page(error: Option<&str>) -> String {
+    let error = error
+        .map(|e| format!("<p><strong>{}</strong></p>\n", escape(e)))
+        .unwrap_or_default();
+    page(
+        "Password required",
+        &format!(
+            r#"{error}<p>This link is protected. Enter the password to continue.</p>
+<form method="post" action="">
+<input type="password" name="password" autofocus required>
+<button type="submit">Continue</button>
+</form>"#,
+            error = error,
+        ),
+    )
+}
+
+//不受信任的目标地址，跳转前先展示警告页，由用户确认后再访问
+pub fn interstitial_page(url: &str) -> String {
+    let url = escape(url);
+    page(
+        "You are leaving this site",
+        &format!(
+            r#"<p><strong>Warning:</strong> this link points to a site we have not verified.</p>
+<p>Destination: <code>{url}</code></p>
+<p><a href="{url}" rel="noopener noreferrer">Continue to the destination</a></p>"#,
+            url = url,
+        ),
+    )
+}
+
+//检查任务认为目标地址已经失效，用户仍然可以继续访问
+pub fn unavailable_page(url: &str, status: Option<i16>) -> String {
+    let url = escape(url);
+    let reason = match status {
+        Some(status) => format!("it returned HTTP {}", status),
+        None => "it could not be reached".to_string(),
+    };
+    page(
+        "Destination unavailable",
+        &format!(
+            r#"<p>The destination of this link seems to be gone: {reason} the last time we checked.</p>
+<p>Destination: <code>{url}</code></p>
+<p><a href="{url}" rel="noopener noreferrer">Try it anyway</a></p>"#,
+            reason = reason,
+            url = url,
+        ),
+    )
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>{title}</title><meta name="robots" content="noindex"></head>
+<body>
+<h1>{title}</h1>
+{body}
+</body>
+</html>"#,
+        title = escape(title),
+        body = body,
+    )
+}
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
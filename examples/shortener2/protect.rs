@@ -0,0 +1,173 @@
+use std::sync::Arc;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::{
+    abuse::check_enabled,
+    error::{AppForm, AppPath},
+    host::Host,
+    preview::password_page,
+    ratelimit::limit_password,
+    redirect_to,
+    routing::Visitor,
+    DbState, MyError,
+};
+
+pub const MAX_PASSWORD_LEN: usize = 256;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UnlockForm {
+    password: String,
+}
+
+//数据库中只保存 argon2 的 PHC 格式哈希
+pub fn hash_password(password: &str) -> Result<String, MyError> {
+    if password.is_empty() || password.chars().count() > MAX_PASSWORD_LEN {
+        return Err(MyError::BadRequest(format!(
+            "password must be between 1 and {} characters",
+            MAX_PASSWORD_LEN
+        )));
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| MyError::Internal(format!("failed to hash password: {}", e)))
+}
+
+//导入时原样保存导出文件中的哈希，只接受 argon2 的 PHC 格式
+pub fn check_password_hash(hash: &str) -> Result<(), MyError> {
+    match PasswordHash::new(hash) {
+        Ok(hash) if hash.algorithm.as_str().starts_with("argon2") => Ok(()),
+        _ => Err(MyError::BadRequest(
+            "password_hash must be an argon2 hash".to_string(),
+        )),
+    }
+}
+
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, MyError> {
+    let hash = PasswordHash::new(hash)
+        .map_err(|e| MyError::Internal(format!("invalid password hash: {}", e)))?;
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_ok())
+}
+
+//POST /:id 提交密码页的表单，密码正确时计一次点击并 303 跳转
+//和 GET /:id 共用按客户端的跳转限流，另外每个短链接的密码尝试次数单独限流，避免暴力猜测密码
+#[utoipa::path(
+    post,
+    path = "/{id}",
+    tag = "links",
+    params(("id" = String, Path, description = "short link id")),
+    request_body(content = UnlockForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "password accepted, redirect to the destination",
+            headers(("location" = String, description = "destination url"))),
+        (status = 200, description = "interstitial warning page", content_type = "text/html"),
+        (status = 401, description = "wrong password, the form is shown again", content_type = "text/html"),
+        (status = 404, description = "short link not found", body = ErrorBody),
+        (status = 410, description = "link disabled for abuse or click limit reached", body = ErrorBody),
+        (status = 429, description = "rate limited or too many password attempts for the link, see Retry-After", body = ErrorBody),
+    )
+)]
+pub async fn unlock(
+    State(state): State<Arc<DbState>>,
+    AppPath(id): AppPath<String>,
+    host: Host,
+    visitor: Visitor,
+    headers: HeaderMap,
+    AppForm(form): AppForm<UnlockForm>,
+) -> Result<Response, MyError> {
+    let link = state.get_link(&host.name, &id).await?;
+    check_enabled(&link)?;
+    if let Some(hash) = link.password_hash {
+        limit_password(&state, &host.name, &id).await?;
+        //argon2 比较耗 CPU，放到阻塞线程池中执行
+        let ok = tokio::task::spawn_blocking(move || verify_password(&form.password, &hash))
+            .await
+            .map_err(|e| MyError::Internal(format!("password check failed: {}", e)))??;
+        if !ok {
+            let page = password_page(Some("Wrong password, please try again."));
+            return Ok((StatusCode::UNAUTHORIZED, Html(page)).into_response());
+        }
+    }
+    match state.find_link(&host.name, &id, true).await? {
+        Some(link) => {
+            redirect_to(
+                &state,
+                &link,
+                &visitor,
+                &headers,
+                StatusCode::SEE_OTHER,
+                true,
+            )
+            .await
+        }
+        None => Err(MyError::UrlNotFound(id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use http::Request;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        ratelimit::Limit,
+        testing::{self, create, send_body, TestResponse},
+    };
+
+    async fn submit(state: &Arc<DbState>, id: &str, password: &str) -> TestResponse {
+        send_body(
+            state,
+            Request::post(format!("/{}", id))
+                .header("content-type", "application/x-www-form-urlencoded"),
+            Body::from(format!("password={}", password)),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn password_attempts_are_limited_per_link() {
+        let mut config = testing::config();
+        config.rate_limit.enabled = true;
+        config.rate_limit.password = Limit {
+            capacity: 2,
+            refill_per_sec: 0.001,
+        };
+        let state = testing::state(config);
+        let body = |url: &str| json!({ "url": url, "password": "hunter2" });
+        let locked = create(&state, body("https://www.rust-lang.org/")).await;
+        let other = create(&state, body("https://crates.io/")).await;
+
+        for _ in 0..2 {
+            let res = submit(&state, &locked, "wrong").await;
+            assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        }
+        //用完之后正确的密码也要等待
+        let res = submit(&state, &locked, "hunter2").await;
+        assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.json()["code"], "rate_limited", "{}", res.body);
+        let retry_after = res.headers["retry-after"].to_str().unwrap().to_string();
+        assert_eq!(
+            res.json()["details"]["retry_after"].to_string(),
+            retry_after
+        );
+        //其他短链接不受影响
+        let res = submit(&state, &other, "hunter2").await;
+        assert_eq!(res.status, StatusCode::SEE_OTHER);
+    }
+}
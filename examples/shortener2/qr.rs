@@ -0,0 +1,151 @@
+use std::{io::Cursor, sync::Arc};
+
+use axum::{extract::State, response::IntoResponse};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use http::header::CONTENT_TYPE;
+use image::{ImageFormat, Luma};
+use qrcode::{render::svg, QrCode};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::{AppPath, AppQuery},
+    host::Host,
+    DbState, MyError,
+};
+
+const MIN_SIZE: u32 = 64;
+const MAX_SIZE: u32 = 2048;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+//纠错等级，等级越高能容忍的污损越多，二维码也越密
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+pub enum EcLevel {
+    L,
+    M,
+    Q,
+    H,
+}
+
+impl From<EcLevel> for qrcode::EcLevel {
+    fn from(level: EcLevel) -> Self {
+        match level {
+            EcLevel::L => qrcode::EcLevel::L,
+            EcLevel::M => qrcode::EcLevel::M,
+            EcLevel::Q => qrcode::EcLevel::Q,
+            EcLevel::H => qrcode::EcLevel::H,
+        }
+    }
+}
+
+//二维码默认参数，可以被请求中的 size/ec 覆盖
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QrConfig {
+    pub size: u32,
+    pub ec_level: EcLevel,
+}
+
+impl Default for QrConfig {
+    fn default() -> Self {
+        Self {
+            size: 256,
+            ec_level: EcLevel::M,
+        }
+    }
+}
+
+impl QrConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_SIZE..=MAX_SIZE).contains(&self.size) {
+            return Err(format!(
+                "qr.size: must be between {} and {}, got {}",
+                MIN_SIZE, MAX_SIZE, self.size
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QrQuery {
+    #[serde(default)]
+    format: QrFormat,
+    //二维码边长（像素），超出范围时截断到 64..=2048
+    size: Option<u32>,
+    ec: Option<EcLevel>,
+}
+
+//GET /:id/qr?format=png|svg&size=256&ec=M 返回短链接的二维码
+#[utoipa::path(
+    get,
+    path = "/{id}/qr",
+    tag = "links",
+    params(("id" = String, Path, description = "short link id"), QrQuery),
+    responses(
+        (status = 200, description = "QR code image", content_type = ["image/png", "image/svg+xml"]),
+        (status = 400, description = "invalid query", body = ErrorBody),
+        (status = 404, description = "short link not found", body = ErrorBody),
+    )
+)]
+pub async fn qr_code(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    AppPath(id): AppPath<String>,
+    AppQuery(query): AppQuery<QrQuery>,
+) -> Result<impl IntoResponse, MyError> {
+    //确认短链接存在，不为不存在的 id 生成二维码
+    state.get_url(&host.name, &id).await?;
+    let size = query
+        .size
+        .unwrap_or(state.config.qr.size)
+        .clamp(MIN_SIZE, MAX_SIZE);
+    let ec = query.ec.unwrap_or(state.config.qr.ec_level);
+    let (content_type, body) = render(&host.short_url(&id), query.format, size, ec)?;
+    Ok(([(CONTENT_TYPE, content_type)], body))
+}
+
+//按默认参数生成 png 格式的 data uri，用于创建短链接时直接返回二维码
+pub fn data_uri(data: &str, config: &QrConfig) -> Result<String, MyError> {
+    let (content_type, body) = render(data, QrFormat::Png, config.size, config.ec_level)?;
+    Ok(format!(
+        "data:{};base64,{}",
+        content_type,
+        STANDARD.encode(body)
+    ))
+}
+
+fn render(
+    data: &str,
+    format: QrFormat,
+    size: u32,
+    ec: EcLevel,
+) -> Result<(&'static str, Vec<u8>), MyError> {
+    let code = QrCode::with_error_correction_level(data, ec.into())
+        .map_err(|e| MyError::Internal(format!("failed to encode qr code: {}", e)))?;
+    match format {
+        QrFormat::Png => {
+            let image = code.render::<Luma<u8>>().min_dimensions(size, size).build();
+            let mut buf = Cursor::new(Vec::new());
+            image
+                .write_to(&mut buf, ImageFormat::Png)
+                .map_err(|e| MyError::Internal(format!("failed to encode png: {}", e)))?;
+            Ok(("image/png", buf.into_inner()))
+        }
+        QrFormat::Svg => {
+            let image = code
+                .render::<svg::Color>()
+                .min_dimensions(size, size)
+                .build();
+            Ok(("image/svg+xml", image.into_bytes()))
+        }
+    }
+}
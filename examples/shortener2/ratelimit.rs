@@ -0,0 +1,353 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use http::{Extensions, HeaderMap};
+use serde::Deserialize;
+
+use crate::{auth::api_key, DbState, MyError};
+
+//清理后最多保留这么多个桶，避免内存无限增长
+const MAX_BUCKETS: usize = 100_000;
+//每处理这么多次请求清理一次已经回满的桶
+const SWEEP_EVERY: usize = 1_000;
+
+//令牌桶参数：桶容量即允许的突发请求数，之后按 refill_per_sec 的速度恢复
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    pub capacity: u32,
+    pub refill_per_sec: f64,
+}
+
+impl Limit {
+    fn validate(&self, name: &str) -> Result<(), String> {
+        if self.capacity == 0 {
+            return Err(format!(
+                "rate_limit.{}.capacity: must be greater than 0",
+                name
+            ));
+        }
+        if !self.refill_per_sec.is_finite() || self.refill_per_sec <= 0.0 {
+            return Err(format!(
+                "rate_limit.{}.refill_per_sec: must be greater than 0",
+                name
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    //服务部署在反向代理后面时使用 X-Forwarded-For 中的第一个地址作为客户端 IP
+    pub trust_forwarded_for: bool,
+    //POST /、POST /batch 和 POST /:id/report
+    pub create: Limit,
+    //GET /:id
+    pub redirect: Limit,
+    //POST /:id 提交密码，按短链接计数，不区分客户端，避免换 IP 暴力猜测同一个短链接的密码
+    pub password: Limit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            trust_forwarded_for: false,
+            create: Limit {
+                capacity: 20,
+                refill_per_sec: 0.5,
+            },
+            redirect: Limit {
+                capacity: 100,
+                refill_per_sec: 20.0,
+            },
+            //连续 5 次之后每分钟一次
+            password: Limit {
+                capacity: 5,
+                refill_per_sec: 1.0 / 60.0,
+            },
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        self.create.validate("create")?;
+        self.redirect.validate("redirect")?;
+        self.password.validate("password")
+    }
+}
+
+//限流状态的存储，默认保存在进程内存中，多实例部署时可以换成共享存储（例如 Redis）
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    //从 key 对应的桶中取一个令牌，成功返回 None，否则返回需要等待的时间
+    async fn acquire(&self, key: &str, limit: &Limit) -> Result<Option<Duration>, MyError>;
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    //从空桶回满需要的时间，不同的限流参数不同，清理时按各自的时间判断
+    full_after: Duration,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &Limit, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.refill_per_sec).min(limit.capacity as f64);
+        self.updated = now;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryRateLimitStore {
+    buckets: DashMap<String, Bucket>,
+    requests: AtomicUsize,
+}
+
+impl MemoryRateLimitStore {
+    //回满的桶和新建的桶没有区别，可以直接删除
+    //仍然超过 max_buckets 时按最后使用时间删除最久没用的桶，这些 key 下次请求时得到一个满的桶
+    fn sweep(&self, now: Instant, max_buckets: usize) {
+        self.buckets
+            .retain(|_, b| now.duration_since(b.updated) < b.full_after);
+        let excess = self.buckets.len().saturating_sub(max_buckets);
+        if excess == 0 {
+            return;
+        }
+        let mut oldest: Vec<(Instant, String)> = self
+            .buckets
+            .iter()
+            .map(|entry| (entry.updated, entry.key().clone()))
+            .collect();
+        oldest.select_nth_unstable(excess - 1);
+        for (_, key) in &oldest[..excess] {
+            self.buckets.remove(key);
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn acquire(&self, key: &str, limit: &Limit) -> Result<Option<Duration>, MyError> {
+        let now = Instant::now();
+        if self.requests.fetch_add(1, Ordering::Relaxed) % SWEEP_EVERY == SWEEP_EVERY - 1 {
+            self.sweep(now, MAX_BUCKETS);
+        }
+        let mut bucket = self.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: limit.capacity as f64,
+            updated: now,
+            full_after: Duration::from_secs_f64(limit.capacity as f64 / limit.refill_per_sec),
+        });
+        bucket.refill(limit, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(None);
+        }
+        let wait = (1.0 - bucket.tokens) / limit.refill_per_sec;
+        Ok(Some(Duration::from_secs_f64(wait)))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Scope {
+    Create,
+    Redirect,
+}
+
+pub async fn limit_create(state: State<Arc<DbState>>, req: Request, next: Next) -> Response {
+    limit(state, Scope::Create, req, next).await
+}
+
+pub async fn limit_redirect(state: State<Arc<DbState>>, req: Request, next: Next) -> Response {
+    limit(state, Scope::Redirect, req, next).await
+}
+
+//每次提交某个短链接的密码前调用，用完后在 Retry-After 秒内拒绝该短链接的所有密码尝试
+pub async fn limit_password(state: &DbState, domain: &str, id: &str) -> Result<(), MyError> {
+    let config = &state.config.rate_limit;
+    if !config.enabled {
+        return Ok(());
+    }
+    let key = format!("password:{}/{}", domain, id);
+    match state.rate_limiter.acquire(&key, &config.password).await? {
+        None => Ok(()),
+        Some(wait) => Err(MyError::RateLimited(wait.as_secs_f64().ceil() as u64)),
+    }
+}
+
+async fn limit(
+    State(state): State<Arc<DbState>>,
+    scope: Scope,
+    req: Request,
+    next: Next,
+) -> Response {
+    let config = &state.config.rate_limit;
+    if !config.enabled {
+        return next.run(req).await;
+    }
+    let (name, limit) = match scope {
+        Scope::Create => ("create", &config.create),
+        Scope::Redirect => ("redirect", &config.redirect),
+    };
+    let key = format!("{}:{}", name, client_key(&state, &req));
+    match state.rate_limiter.acquire(&key, limit).await {
+        Ok(None) => next.run(req).await,
+        //向上取整，保证客户端等待 Retry-After 秒后一定能拿到令牌
+        Ok(Some(wait)) => MyError::RateLimited(wait.as_secs_f64().ceil() as u64).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+//带了有效 api key 的请求按用户限流，否则按客户端 IP 限流
+//无效的 api key 也按 IP 限流，避免随意伪造 key 绕过限制
+fn client_key(state: &DbState, req: &Request) -> String {
+    let headers = req.headers();
+    if let Some(user) = api_key(headers).and_then(|key| state.api_keys.user(key)) {
+        return format!("user:{}", user.owner);
+    }
+    match client_ip(state, headers, req.extensions()) {
+        Some(ip) => format!("ip:{}", ip),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+//客户端地址，trust_forwarded_for 为 true 时使用 X-Forwarded-For 中的第一个地址
+pub fn client_ip(state: &DbState, headers: &HeaderMap, extensions: &Extensions) -> Option<String> {
+    let forwarded = state
+        .config
+        .rate_limit
+        .trust_forwarded_for
+        .then(|| headers.get("x-forwarded-for")?.to_str().ok())
+        .flatten()
+        .and_then(|v| v.split(',').next())
+        .map(str::trim)
+        .filter(|v| !v.is_empty());
+    if let Some(ip) = forwarded {
+        return Some(ip.to_string());
+    }
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{Request, StatusCode};
+    use serde_json::json;
+
+    use super::*;
+    use crate::testing::{self, create, send};
+
+    const LIMIT: Limit = Limit {
+        capacity: 3,
+        refill_per_sec: 2.0,
+    };
+
+    #[tokio::test]
+    async fn buckets_allow_a_burst_then_ask_to_wait() {
+        let store = MemoryRateLimitStore::default();
+        for _ in 0..3 {
+            assert_eq!(store.acquire("a", &LIMIT).await.unwrap(), None);
+        }
+        //每秒恢复 2 个令牌，最多等待半秒
+        let wait = store.acquire("a", &LIMIT).await.unwrap().unwrap();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(500));
+        //其他 key 有自己的桶
+        assert_eq!(store.acquire("b", &LIMIT).await.unwrap(), None);
+    }
+
+    #[test]
+    fn buckets_refill_up_to_the_capacity() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            updated: start,
+            full_after: Duration::from_secs(2),
+        };
+        bucket.refill(&LIMIT, start + Duration::from_millis(250));
+        assert_eq!(bucket.tokens, 0.5);
+        bucket.refill(&LIMIT, start + Duration::from_secs(1));
+        assert_eq!(bucket.tokens, 2.0);
+        bucket.refill(&LIMIT, start + Duration::from_secs(60));
+        assert_eq!(bucket.tokens, 3.0);
+        assert_eq!(bucket.updated, start + Duration::from_secs(60));
+    }
+
+    #[test]
+    fn sweeps_drop_full_buckets_then_the_oldest() {
+        let store = MemoryRateLimitStore::default();
+        let now = Instant::now() + Duration::from_secs(10);
+        //(key, 距上次使用的秒数, 回满需要的秒数)
+        for (key, idle, full_after) in [
+            ("full", 5, 2),
+            ("old", 8, 60),
+            ("new", 1, 60),
+            ("newer", 0, 60),
+        ] {
+            let bucket = Bucket {
+                tokens: 0.0,
+                updated: now - Duration::from_secs(idle),
+                full_after: Duration::from_secs(full_after),
+            };
+            store.buckets.insert(key.to_string(), bucket);
+        }
+        store.sweep(now, 2);
+        let mut keys: Vec<String> = store.buckets.iter().map(|e| e.key().clone()).collect();
+        keys.sort();
+        assert_eq!(keys, ["new", "newer"]);
+    }
+
+    #[test]
+    fn invalid_limits_are_rejected() {
+        for (capacity, refill_per_sec) in [(0, 1.0), (1, 0.0), (1, -1.0), (1, f64::NAN)] {
+            let limit = Limit {
+                capacity,
+                refill_per_sec,
+            };
+            assert!(limit.validate("redirect").is_err(), "{:?}", limit);
+        }
+        assert!(RateLimitConfig::default().validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn reports_share_the_create_limit() {
+        let mut config = testing::config();
+        config.rate_limit.enabled = true;
+        config.rate_limit.create = Limit {
+            capacity: 2,
+            refill_per_sec: 0.001,
+        };
+        let state = testing::state(config);
+        let id = create(&state, json!({ "url": "https://www.rust-lang.org/" })).await;
+        let report = || {
+            send(
+                &state,
+                Request::post(format!("/{}/report", id)),
+                Some(json!({ "reason": "spam" })),
+            )
+        };
+        assert_eq!(report().await.status, StatusCode::ACCEPTED);
+        let res = report().await;
+        assert_eq!(res.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.json()["code"], "rate_limited");
+    }
+}
@@ -0,0 +1,399 @@
+use std::{net::IpAddr, sync::Arc};
+
+use axum::{async_trait, extract::FromRequestParts};
+use http::{
+    header::{ACCEPT_LANGUAGE, USER_AGENT},
+    request::Parts,
+    HeaderMap,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    geoip::{normalize_country, GeoIp},
+    ratelimit::client_ip,
+    store::LinkRecord,
+    DbState, MyError,
+};
+
+pub const MAX_RULES: usize = 20;
+//语言标签最长的长度，例如 zh-hant-tw
+const MAX_LANGUAGE_LEN: usize = 35;
+
+//按 User-Agent 粗略区分的设备类型
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Device {
+    #[default]
+    Desktop,
+    Mobile,
+    Tablet,
+    Bot,
+}
+
+impl Device {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Desktop => "desktop",
+            Self::Mobile => "mobile",
+            Self::Tablet => "tablet",
+            Self::Bot => "bot",
+        }
+    }
+
+    //没有 User-Agent 时按 desktop 处理
+    pub fn from_user_agent(ua: &str) -> Self {
+        let ua = ua.to_lowercase();
+        let has = |words: &[&str]| words.iter().any(|w| ua.contains(w));
+        if has(&["bot", "crawler", "spider", "slurp", "facebookexternalhit"]) {
+            Self::Bot
+        } else if has(&["ipad", "tablet", "kindle", "silk"])
+            || (ua.contains("android") && !ua.contains("mobile"))
+        {
+            Self::Tablet
+        } else if has(&[
+            "mobi",
+            "iphone",
+            "ipod",
+            "android",
+            "windows phone",
+            "opera mini",
+        ]) {
+            Self::Mobile
+        } else {
+            Self::Desktop
+        }
+    }
+}
+
+//一条跳转规则，设置了的条件需要全部满足，同一个条件中的多个值满足一个即可
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Rule {
+    pub url: String,
+    //数字越大越先匹配，相同时按创建时的顺序
+    #[serde(default)]
+    pub priority: i32,
+    //访客所在的国家，由 geoip 数据库按客户端地址查出
+    #[serde(default)]
+    #[schema(example = json!(["CN", "TW"]))]
+    pub countries: Vec<String>,
+    #[serde(default)]
+    pub devices: Vec<Device>,
+    //Accept-Language 中访客最优先的语言，zh 匹配 zh 和 zh-cn，zh-cn 只匹配 zh-cn
+    #[serde(default)]
+    #[schema(example = json!(["zh"]))]
+    pub languages: Vec<String>,
+}
+
+impl Rule {
+    fn matches(&self, visitor: &Visitor) -> bool {
+        let country = self.countries.is_empty()
+            || visitor
+                .country
+                .as_ref()
+                .is_some_and(|c| self.countries.contains(c));
+        let device = self.devices.is_empty() || self.devices.contains(&visitor.device);
+        let language = self.languages.is_empty()
+            || visitor
+                .language
+                .as_deref()
+                .is_some_and(|l| self.languages.iter().any(|r| language_matches(l, r)));
+        country && device && language
+    }
+}
+
+fn language_matches(language: &str, rule: &str) -> bool {
+    language
+        .strip_prefix(rule)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+}
+
+//规范化国家代码和语言，按 priority 从高到低排序，rules 中的 url 需要调用方事先规范化
+pub fn check_rules(rules: &mut [Rule]) -> Result<(), MyError> {
+    if rules.len() > MAX_RULES {
+        return Err(MyError::BadRequest(format!(
+            "rules must have at most {} entries",
+            MAX_RULES
+        )));
+    }
+    for rule in rules.iter_mut() {
+        if rule.countries.is_empty() && rule.devices.is_empty() && rule.languages.is_empty() {
+            return Err(MyError::BadRequest(format!(
+                "rule for {} needs countries, devices or languages",
+                rule.url
+            )));
+        }
+        for country in rule.countries.iter_mut() {
+            *country = normalize_country(country)
+                .ok_or_else(|| MyError::BadRequest(format!("invalid country: {}", country)))?;
+        }
+        for language in rule.languages.iter_mut() {
+            *language = normalize_language(language)
+                .ok_or_else(|| MyError::BadRequest(format!("invalid language: {}", language)))?;
+        }
+    }
+    //sort_by_key 是稳定排序
+    rules.sort_by_key(|r| std::cmp::Reverse(r.priority));
+    Ok(())
+}
+
+//BCP 47 语言标签，例如 en、zh-CN，转换为小写
+fn normalize_language(language: &str) -> Option<String> {
+    let language = language.trim().to_lowercase();
+    let valid = language.len() <= MAX_LANGUAGE_LEN
+        && language.split('-').enumerate().all(|(i, part)| {
+            (1..=8).contains(&part.len())
+                && match i {
+                    0 => part.chars().all(|c| c.is_ascii_lowercase()),
+                    _ => part.chars().all(|c| c.is_ascii_alphanumeric()),
+                }
+        });
+    valid.then_some(language)
+}
+
+//Accept-Language 中 q 值最高的语言，忽略 * 和 q=0
+fn preferred_language(value: &str) -> Option<String> {
+    let mut best: Option<(String, f32)> = None;
+    for item in value.split(',') {
+        let mut parts = item.split(';');
+        let Some(language) = parts.next().and_then(normalize_language) else {
+            continue;
+        };
+        let q = match parts.find_map(|p| p.trim().strip_prefix("q=")) {
+            Some(q) => match q.trim().parse::<f32>() {
+                Ok(q) => q,
+                Err(_) => continue,
+            },
+            None => 1.0,
+        };
+        //q 相同时保留靠前的
+        if q > 0.0 && best.as_ref().is_none_or(|(_, best)| q > *best) {
+            best = Some((language, q));
+        }
+    }
+    best.map(|(language, _)| language)
+}
+
+//跳转请求的访客信息
+#[derive(Debug, Clone, Default)]
+pub struct Visitor {
+    pub country: Option<String>,
+    pub device: Device,
+    pub language: Option<String>,
+}
+
+impl Visitor {
+    pub fn new(headers: &HeaderMap, ip: Option<IpAddr>, geoip: &GeoIp) -> Self {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        Self {
+            country: ip.and_then(|ip| geoip.lookup(ip)).map(String::from),
+            device: header(USER_AGENT)
+                .map(Device::from_user_agent)
+                .unwrap_or_default(),
+            language: header(ACCEPT_LANGUAGE).and_then(preferred_language),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<DbState>> for Visitor {
+    type Rejection = MyError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<DbState>,
+    ) -> Result<Self, Self::Rejection> {
+        let ip = client_ip(state, &parts.headers, &parts.extensions).and_then(|ip| ip.parse().ok());
+        Ok(Self::new(&parts.headers, ip, &state.geoip))
+    }
+}
+
+//第一条匹配的规则的目标地址，都不匹配时返回 None，由调用方使用 url 或者 destinations
+pub fn route<'a>(link: &'a LinkRecord, visitor: &Visitor) -> Option<&'a str> {
+    link.rules
+        .iter()
+        .find(|rule| rule.matches(visitor))
+        .map(|rule| rule.url.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(url: &str, priority: i32) -> Rule {
+        Rule {
+            url: url.to_string(),
+            priority,
+            countries: Vec::new(),
+            devices: Vec::new(),
+            languages: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn preferred_language_uses_the_highest_q() {
+        for (header, expected) in [
+            ("en-US,en;q=0.9", Some("en-us")),
+            ("fr;q=0.5, de;q=0.8, en;q=0.1", Some("de")),
+            //q 相同时取靠前的
+            ("fr;q=0.5, de;q=0.5", Some("fr")),
+            //q=0 表示不接受
+            ("en;q=0, fr;q=0.1", Some("fr")),
+            ("en;q=0", None),
+            //* 不是具体的语言
+            ("*, zh;q=0.5", Some("zh")),
+            ("*", None),
+            //q 无法解析的项被忽略
+            ("en;q=abc, ja;q=0.2", Some("ja")),
+            ("ZH-Hant-TW", Some("zh-hant-tw")),
+            ("", None),
+        ] {
+            assert_eq!(
+                preferred_language(header).as_deref(),
+                expected,
+                "{}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn languages_are_normalized() {
+        for (language, expected) in [
+            ("en", Some("en")),
+            (" zh-CN ", Some("zh-cn")),
+            ("sr-Latn-RS", Some("sr-latn-rs")),
+            ("de-1996", Some("de-1996")),
+            ("", None),
+            ("*", None),
+            ("en-", None),
+            ("en_US", None),
+            ("1en", None),
+            ("toolonglanguage", None),
+        ] {
+            assert_eq!(
+                normalize_language(language).as_deref(),
+                expected,
+                "{}",
+                language
+            );
+        }
+    }
+
+    #[test]
+    fn languages_match_by_prefix_subtag() {
+        let visitor = |language: &str| Visitor {
+            language: Some(language.to_string()),
+            ..Visitor::default()
+        };
+        let zh = Rule {
+            languages: vec!["zh".to_string()],
+            ..rule("https://example.cn/", 0)
+        };
+        let zh_tw = Rule {
+            languages: vec!["zh-tw".to_string()],
+            ..rule("https://example.tw/", 0)
+        };
+        //zh 同时匹配各个地区
+        assert!(zh.matches(&visitor("zh")));
+        assert!(zh.matches(&visitor("zh-cn")));
+        assert!(!zh.matches(&visitor("zht")));
+        //带地区的规则不回退到只有语言的访客
+        assert!(zh_tw.matches(&visitor("zh-tw")));
+        assert!(!zh_tw.matches(&visitor("zh")));
+        assert!(!zh_tw.matches(&visitor("zh-cn")));
+        assert!(!zh.matches(&Visitor::default()));
+    }
+
+    #[test]
+    fn devices_are_detected_from_the_user_agent() {
+        for (ua, expected) in [
+            (
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 Chrome/120.0 Safari/537.36",
+                Device::Desktop,
+            ),
+            (
+                "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 Mobile/15E148",
+                Device::Mobile,
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 Chrome/120.0 Mobile Safari/537.36",
+                Device::Mobile,
+            ),
+            (
+                "Mozilla/5.0 (Linux; Android 13; SM-X700) AppleWebKit/537.36 Chrome/120.0 Safari/537.36",
+                Device::Tablet,
+            ),
+            (
+                "Mozilla/5.0 (iPad; CPU OS 17_0 like Mac OS X) AppleWebKit/605.1.15 Mobile/15E148",
+                Device::Tablet,
+            ),
+            ("Googlebot/2.1 (+http://www.google.com/bot.html)", Device::Bot),
+            ("facebookexternalhit/1.1", Device::Bot),
+            ("curl/8.5.0", Device::Desktop),
+            ("", Device::Desktop),
+        ] {
+            assert_eq!(Device::from_user_agent(ua), expected, "{}", ua);
+        }
+    }
+
+    #[test]
+    fn rules_are_normalized_and_sorted_by_priority() {
+        let mut rules = vec![
+            Rule {
+                countries: vec!["cn".to_string()],
+                ..rule("https://example.com/low", 0)
+            },
+            Rule {
+                languages: vec!["ZH-TW".to_string()],
+                ..rule("https://example.com/high", 10)
+            },
+            Rule {
+                devices: vec![Device::Mobile],
+                ..rule("https://example.com/also-low", 0)
+            },
+        ];
+        check_rules(&mut rules).unwrap();
+        let urls: Vec<&str> = rules.iter().map(|r| r.url.as_str()).collect();
+        //priority 相同时保持原来的顺序
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/high",
+                "https://example.com/low",
+                "https://example.com/also-low"
+            ]
+        );
+        assert_eq!(rules[0].languages, ["zh-tw"]);
+        assert_eq!(rules[1].countries, ["CN"]);
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let cases = [
+            vec![rule("https://example.com/", 0)],
+            vec![Rule {
+                countries: vec!["CHN".to_string()],
+                ..rule("https://example.com/", 0)
+            }],
+            vec![Rule {
+                languages: vec!["en_US".to_string()],
+                ..rule("https://example.com/", 0)
+            }],
+            vec![
+                Rule {
+                    devices: vec![Device::Bot],
+                    ..rule("https://example.com/", 0)
+                };
+                MAX_RULES + 1
+            ],
+        ];
+        for mut rules in cases {
+            assert!(
+                matches!(check_rules(&mut rules), Err(MyError::BadRequest(_))),
+                "{:?}",
+                rules
+            );
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use http::{header::COOKIE, HeaderMap, HeaderValue};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{store::LinkRecord, MyError};
+
+pub const MAX_DESTINATIONS: usize = 10;
+pub const MAX_WEIGHT: u32 = 10_000;
+//sticky 链接记录访客分组的 cookie，名字为 sl_<id>，值为目标的下标
+const COOKIE_PREFIX: &str = "sl_";
+const COOKIE_MAX_AGE: u64 = 30 * 24 * 3600;
+
+//多目标短链接的一个目标，clicks 为跳转到这个目标的次数，创建时忽略
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Destination {
+    pub url: String,
+    //按权重随机分配流量，例如 [1, 1] 为各一半
+    #[serde(default = "default_weight")]
+    #[schema(example = 1)]
+    pub weight: u32,
+    #[serde(default)]
+    pub clicks: i64,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+//destinations 需要是 2 个以上已规范化的 url，其中一个是短链接本身的 url
+pub fn check_destinations(url: &str, destinations: &[Destination]) -> Result<(), MyError> {
+    if !(2..=MAX_DESTINATIONS).contains(&destinations.len()) {
+        return Err(MyError::BadRequest(format!(
+            "destinations must have between 2 and {} entries",
+            MAX_DESTINATIONS
+        )));
+    }
+    if let Some(d) = destinations
+        .iter()
+        .find(|d| !(1..=MAX_WEIGHT).contains(&d.weight))
+    {
+        return Err(MyError::BadRequest(format!(
+            "weight of {} must be between 1 and {}",
+            d.url, MAX_WEIGHT
+        )));
+    }
+    if !destinations.iter().any(|d| d.url == url) {
+        return Err(MyError::BadRequest(
+            "url must be one of the destinations".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+//选择本次跳转的目标，返回目标的下标和需要写入的 cookie，普通短链接返回 None
+//sticky 链接优先使用 cookie 中记录的目标，没有时按权重随机选择并写入 cookie
+pub fn choose(link: &LinkRecord, headers: &HeaderMap) -> Option<(usize, Option<HeaderValue>)> {
+    let destinations = &link.destinations;
+    if destinations.is_empty() {
+        return None;
+    }
+    if link.sticky {
+        if let Some(index) = cookie(headers, &format!("{}{}", COOKIE_PREFIX, link.id))
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|i| *i < destinations.len())
+        {
+            return Some((index, None));
+        }
+    }
+    let index = pick(destinations, &mut rand::thread_rng());
+    let set_cookie = link
+        .sticky
+        .then(|| {
+            HeaderValue::from_str(&format!(
+                "{}{}={}; Path=/{}; Max-Age={}; HttpOnly; SameSite=Lax",
+                COOKIE_PREFIX, link.id, index, link.id, COOKIE_MAX_AGE
+            ))
+            .ok()
+        })
+        .flatten();
+    Some((index, set_cookie))
+}
+
+//按权重随机选择一个下标
+fn pick(destinations: &[Destination], rng: &mut impl Rng) -> usize {
+    let total: u32 = destinations.iter().map(|d| d.weight).sum();
+    let mut n = rng.gen_range(0..total.max(1));
+    for (i, d) in destinations.iter().enumerate() {
+        if n < d.weight {
+            return i;
+        }
+        n -= d.weight;
+    }
+    destinations.len() - 1
+}
+
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::store::{MemoryStore, NewLink, Store};
+
+    fn destination(url: &str, weight: u32) -> Destination {
+        Destination {
+            url: url.to_string(),
+            weight,
+            clicks: 0,
+        }
+    }
+
+    async fn link(sticky: bool) -> LinkRecord {
+        let store = MemoryStore::default();
+        let link = NewLink {
+            destinations: vec![
+                destination("https://example.com/a", 1),
+                destination("https://example.com/b", 1),
+            ],
+            sticky,
+            ..NewLink::new("https://example.com/a", "alice")
+        };
+        store.insert_link("ab", &link).await.unwrap();
+        store.get_link("", "ab").await.unwrap().unwrap()
+    }
+
+    fn with_cookie(cookie: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(cookie).unwrap());
+        headers
+    }
+
+    #[test]
+    fn picks_follow_the_weights() {
+        let destinations = [
+            destination("https://example.com/a", 1),
+            destination("https://example.com/b", 3),
+            destination("https://example.com/c", 0),
+        ];
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut counts = [0; 3];
+        for _ in 0..10_000 {
+            counts[pick(&destinations, &mut rng)] += 1;
+        }
+        //1:3，各自允许 2% 的偏差，权重为 0 的目标不会被选中
+        assert!((2300..=2700).contains(&counts[0]), "{:?}", counts);
+        assert!((7300..=7700).contains(&counts[1]), "{:?}", counts);
+        assert_eq!(counts[2], 0);
+    }
+
+    #[tokio::test]
+    async fn sticky_links_reuse_a_valid_cookie() {
+        let link = link(true).await;
+        let (index, cookie) = choose(&link, &HeaderMap::new()).unwrap();
+        let cookie = cookie.expect("sticky links set a cookie");
+        let cookie = cookie.to_str().unwrap();
+        assert!(
+            cookie.starts_with(&format!("sl_ab={}; Path=/ab;", index)),
+            "{}",
+            cookie
+        );
+
+        for value in ["0", "1"] {
+            let headers = with_cookie(&format!("theme=dark; sl_ab={}", value));
+            assert_eq!(
+                choose(&link, &headers),
+                Some((value.parse().unwrap(), None))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn tampered_cookies_are_replaced() {
+        let link = link(true).await;
+        for cookie in ["sl_ab=2", "sl_ab=-1", "sl_ab=abc", "sl_ab=", "sl_other=1"] {
+            let (index, set_cookie) = choose(&link, &with_cookie(cookie)).unwrap();
+            assert!(index < 2, "{}", cookie);
+            let set_cookie = set_cookie.unwrap_or_else(|| panic!("{} is replaced", cookie));
+            assert!(set_cookie
+                .to_str()
+                .unwrap()
+                .starts_with(&format!("sl_ab={};", index)));
+        }
+    }
+
+    #[tokio::test]
+    async fn other_links_ignore_cookies() {
+        let link = link(false).await;
+        let (index, set_cookie) = choose(&link, &with_cookie("sl_ab=1")).unwrap();
+        assert!(index < 2);
+        assert_eq!(set_cookie, None);
+        let mut single = link.clone();
+        single.destinations.0.clear();
+        assert_eq!(choose(&single, &HeaderMap::new()), None);
+    }
+}
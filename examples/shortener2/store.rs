@@ -0,0 +1,416 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use serde::Serialize;
+use sqlx::{types::Json, FromRow};
+use utoipa::ToSchema;
+
+use crate::{
+    checker::CheckResult,
+    config::AppConfig,
+    routing::Rule,
+    split::Destination,
+    validate::DEFAULT_REDIRECT_STATUS,
+    webhook::{Delivery, DeliveryOutcome, NewDelivery},
+    MyError,
+};
+
+pub use memory::MemoryStore;
+pub use postgres::PgStore;
+
+mod memory;
+mod postgres;
+
+//database_url 为 memory:// 时使用内存存储，方便本地调试和测试
+pub const MEMORY_URL: &str = "memory://";
+
+//短链接完整记录，用于 /api/links 接口返回
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
+pub struct LinkRecord {
+    //所属的域名，默认域名为空字符串
+    pub domain: String,
+    pub id: String,
+    pub url: String,
+    pub owner: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub clicks: i64,
+    //跳转前强制展示警告页
+    pub interstitial: bool,
+    //管理员禁用的原因，abuse 或 legal，为空表示正常
+    pub disabled_reason: Option<String>,
+    pub disabled_at: Option<DateTime<Utc>>,
+    //301、302、307 或 308
+    pub redirect_status: i16,
+    //跳转时合并到目标地址的参数
+    #[schema(value_type = BTreeMap<String, String>)]
+    pub query_params: Json<BTreeMap<String, String>>,
+    //argon2 哈希，不对外返回
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    //最多可以跳转的次数，达到后返回 410
+    pub max_clicks: Option<i64>,
+    //多目标短链接的目标及各自的点击数，为空表示只跳转到 url
+    #[schema(value_type = Vec<Destination>)]
+    pub destinations: Json<Vec<Destination>>,
+    //同一访客总是跳转到同一个目标
+    pub sticky: bool,
+    //标题、描述和标签，用于搜索
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    //过期后跳转返回 410，为空表示永不过期
+    pub expires_at: Option<DateTime<Utc>>,
+    //最近一次可达性检查的 HTTP 状态码，请求失败时为空，错误信息在 check_error 中
+    pub check_status: Option<i16>,
+    pub check_error: Option<String>,
+    //为空表示还没有检查过
+    pub checked_at: Option<DateTime<Utc>>,
+    //连续检查失败的次数
+    #[serde(skip)]
+    pub check_failures: i32,
+    //连续失败达到 checker.dead_after 次，目标地址可能已经失效
+    pub dead: bool,
+    //按访客跳转到不同目标的规则，已按 priority 排序
+    #[schema(value_type = Vec<Rule>)]
+    pub rules: Json<Vec<Rule>>,
+}
+
+impl LinkRecord {
+    pub fn is_shared(&self) -> bool {
+        SharedFields {
+            interstitial: self.interstitial,
+            redirect_status: self.redirect_status as u16,
+            query_params: &self.query_params,
+            password_hash: &self.password_hash,
+            max_clicks: self.max_clicks,
+            destinations: &self.destinations,
+            title: &self.title,
+            description: &self.description,
+            tags: &self.tags,
+            expires_at: self.expires_at,
+            rules: &self.rules,
+        }
+        .is_shared()
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.max_clicks.is_some_and(|max| self.clicks >= max)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Utc::now())
+    }
+}
+
+//用户提交的滥用举报
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
+pub struct Report {
+    pub id: i64,
+    pub domain: String,
+    pub link_id: String,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+}
+
+//修改目标 url 的一条记录
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
+pub struct Revision {
+    pub id: i64,
+    pub domain: String,
+    pub link_id: String,
+    pub old_url: String,
+    pub new_url: String,
+    //修改人的 owner
+    pub changed_by: String,
+    pub created_at: DateTime<Utc>,
+}
+
+//创建短链接时写入的字段，id 由 IdGenerator 单独生成
+#[derive(Debug, Clone)]
+pub struct NewLink {
+    pub domain: String,
+    pub url: String,
+    pub owner: String,
+    pub interstitial: bool,
+    pub redirect_status: u16,
+    pub query_params: BTreeMap<String, String>,
+    pub password_hash: Option<String>,
+    pub max_clicks: Option<i64>,
+    pub destinations: Vec<Destination>,
+    pub sticky: bool,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub rules: Vec<Rule>,
+    //导入时保留导出文件中的点击数和禁用状态，新建的短链接为 0 和 None
+    pub clicks: i64,
+    pub disabled_reason: Option<String>,
+    pub disabled_at: Option<DateTime<Utc>>,
+}
+
+impl NewLink {
+    //其余字段使用默认值
+    pub fn new(url: impl Into<String>, owner: impl Into<String>) -> Self {
+        Self {
+            domain: String::new(),
+            url: url.into(),
+            owner: owner.into(),
+            interstitial: false,
+            redirect_status: DEFAULT_REDIRECT_STATUS,
+            query_params: BTreeMap::new(),
+            password_hash: None,
+            max_clicks: None,
+            destinations: Vec::new(),
+            sticky: false,
+            title: None,
+            description: None,
+            tags: Vec::new(),
+            expires_at: None,
+            rules: Vec::new(),
+            clicks: 0,
+            disabled_reason: None,
+            disabled_at: None,
+        }
+    }
+
+    pub fn is_shared(&self) -> bool {
+        SharedFields {
+            interstitial: self.interstitial,
+            redirect_status: self.redirect_status,
+            query_params: &self.query_params,
+            password_hash: &self.password_hash,
+            max_clicks: self.max_clicks,
+            destinations: &self.destinations,
+            title: &self.title,
+            description: &self.description,
+            tags: &self.tags,
+            expires_at: self.expires_at,
+            rules: &self.rules,
+        }
+        .is_shared()
+    }
+}
+
+//决定短链接是否按 url 去重的字段，LinkRecord 和 NewLink 共用同一个判断
+//postgres 中的 SHARED_URL 和迁移中 url 唯一索引的条件与 is_shared 对应，store/postgres.rs 中的测试检查三者一致
+struct SharedFields<'a> {
+    interstitial: bool,
+    redirect_status: u16,
+    query_params: &'a BTreeMap<String, String>,
+    password_hash: &'a Option<String>,
+    max_clicks: Option<i64>,
+    destinations: &'a [Destination],
+    title: &'a Option<String>,
+    description: &'a Option<String>,
+    tags: &'a [String],
+    expires_at: Option<DateTime<Utc>>,
+    rules: &'a [Rule],
+}
+
+impl SharedFields<'_> {
+    //普通短链接按 url 去重，展示警告页、改变跳转方式、受保护、限制次数、多目标、带标题标签、会过期或者有跳转规则的短链接每次都单独创建
+    fn is_shared(&self) -> bool {
+        !self.interstitial
+            && self.redirect_status == DEFAULT_REDIRECT_STATUS
+            && self.query_params.is_empty()
+            && self.password_hash.is_none()
+            && self.max_clicks.is_none()
+            && self.destinations.is_empty()
+            && self.title.is_none()
+            && self.description.is_none()
+            && self.tags.is_empty()
+            && self.expires_at.is_none()
+            && self.rules.is_empty()
+    }
+}
+
+//写入的结果，created 为 false 时 id 是同一用户同一 url 已有的普通短链接
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inserted {
+    pub id: String,
+    pub created: bool,
+}
+
+//列出短链接时的过滤条件
+#[derive(Debug, Clone, Default)]
+pub struct LinkFilter {
+    //在标题和描述中全文搜索，多个词之间为 AND，已去掉首尾空白且不为空
+    pub q: Option<String>,
+    //只返回带有这个标签的短链接，已转为小写
+    pub tag: Option<String>,
+    //只返回排在游标之后的短链接，即上一页最后一条之后
+    pub after: Option<Cursor>,
+}
+
+//按 (created_at 倒序, id) 排序时的位置，比 offset 翻页稳定，翻页期间新建的短链接不会造成重复
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: String,
+}
+
+impl Cursor {
+    pub fn of(link: &LinkRecord) -> Self {
+        Self {
+            created_at: link.created_at,
+            id: link.id.clone(),
+        }
+    }
+
+    //link 是否排在游标之后
+    pub fn precedes(&self, link: &LinkRecord) -> bool {
+        link.created_at < self.created_at
+            || (link.created_at == self.created_at && link.id > self.id)
+    }
+}
+
+//内存存储的分词方式，与 Postgres 的 simple 配置接近：按非字母数字切分并转为小写
+pub fn search_words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+}
+
+//短链接存储，生产环境使用 Postgres，测试时使用内存存储
+//短链接由 (domain, id) 唯一确定，domain 为请求的 Host 对应的域名，默认域名为空字符串
+#[async_trait]
+pub trait Store: Send + Sync {
+    async fn migrate(&self) -> Result<(), MyError> {
+        Ok(())
+    }
+    //就绪检查，确认存储可用
+    async fn ping(&self) -> Result<(), MyError> {
+        Ok(())
+    }
+    //退出前关闭连接
+    async fn close(&self) {}
+    //写入一条短链接，同一域名下同一用户的普通短链接的 url 已存在时返回已有的 id，id 冲突时返回 None
+    async fn insert_link(&self, id: &str, link: &NewLink) -> Result<Option<Inserted>, MyError>;
+    //批量写入，ids 与 urls 一一对应且 urls 已去重，返回 url -> 写入结果的映射
+    //任意一个 id 冲突时整批都不写入并返回 None
+    async fn insert_links(
+        &self,
+        domain: &str,
+        ids: &[String],
+        urls: &[String],
+        owner: &str,
+    ) -> Result<Option<HashMap<String, Inserted>>, MyError>;
+    //取 count 个递增的序列号
+    async fn next_sequence(&self, count: usize) -> Result<Vec<u64>, MyError>;
+    async fn get_link(&self, domain: &str, id: &str) -> Result<Option<LinkRecord>, MyError>;
+    //跳转时调用，点击数原子地加一并返回加一后的记录
+    //已禁用、已过期、已达到 max_clicks，或者设置了密码但 unlocked 为 false 时不计数并返回 None
+    async fn resolve_link(
+        &self,
+        domain: &str,
+        id: &str,
+        unlocked: bool,
+    ) -> Result<Option<LinkRecord>, MyError>;
+    //多目标短链接跳转到第 index 个目标时记录一次点击
+    async fn add_destination_click(
+        &self,
+        domain: &str,
+        id: &str,
+        index: usize,
+    ) -> Result<(), MyError>;
+    //按创建时间顺序逐行读取某个域名下的短链接，owner 为 None 时读取全部，用于导出
+    fn stream_links(
+        &self,
+        domain: &str,
+        owner: Option<String>,
+    ) -> BoxStream<'static, Result<LinkRecord, MyError>>;
+    //分页列出某个用户在某个域名下符合 filter 的短链接，按创建时间倒序
+    async fn list_links(
+        &self,
+        domain: &str,
+        owner: &str,
+        filter: &LinkFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<LinkRecord>, MyError>;
+    async fn delete_link(&self, domain: &str, id: &str) -> Result<bool, MyError>;
+    //修改短链接指向的 url 并记录一条修订，新的 url 已被同一域名下的其他短链接使用时返回 Conflict
+    //url 没有变化时不记录修订
+    async fn update_link(
+        &self,
+        domain: &str,
+        id: &str,
+        url: &str,
+        changed_by: &str,
+    ) -> Result<Option<LinkRecord>, MyError>;
+    //按时间倒序列出短链接的修订
+    async fn list_revisions(
+        &self,
+        domain: &str,
+        id: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Revision>, MyError>;
+    async fn get_revision(
+        &self,
+        domain: &str,
+        id: &str,
+        revision: i64,
+    ) -> Result<Option<Revision>, MyError>;
+    //禁用或者恢复（reason 为 None）短链接，链接不存在时返回 None
+    async fn set_disabled(
+        &self,
+        domain: &str,
+        id: &str,
+        reason: Option<&str>,
+    ) -> Result<Option<LinkRecord>, MyError>;
+    //记录一条举报，链接不存在时返回 None
+    async fn add_report(
+        &self,
+        domain: &str,
+        id: &str,
+        reason: &str,
+    ) -> Result<Option<Report>, MyError>;
+    //按提交时间倒序列出所有域名的举报
+    async fn list_reports(&self, limit: i64, offset: i64) -> Result<Vec<Report>, MyError>;
+    //领取最多 limit 条未禁用、从未检查过或者上次检查早于 stale_before 的短链接，checked_at 更新为当前时间
+    async fn claim_checks(
+        &self,
+        limit: i64,
+        stale_before: DateTime<Utc>,
+    ) -> Result<Vec<LinkRecord>, MyError>;
+    //记录检查结果，检查期间 url 已经被修改时忽略
+    async fn record_check(
+        &self,
+        domain: &str,
+        id: &str,
+        url: &str,
+        result: &CheckResult,
+        dead_after: u32,
+    ) -> Result<(), MyError>;
+    //写入 webhook 投递队列
+    async fn enqueue_deliveries(&self, deliveries: &[NewDelivery]) -> Result<(), MyError>;
+    //领取最多 limit 条到期的投递，领取后 next_attempt_at 推迟 lease，其他实例在此期间不会重复领取
+    async fn claim_deliveries(&self, limit: i64, lease: Duration)
+        -> Result<Vec<Delivery>, MyError>;
+    //记录一次投递的结果，attempts 加一
+    async fn finish_delivery(&self, id: i64, outcome: &DeliveryOutcome) -> Result<(), MyError>;
+    //找出最多 limit 条已过期且还没有通知过的短链接，标记为已通知，并写入 deliveries 返回的投递
+    //标记和写入投递在同一个事务中，返回处理的短链接数量
+    async fn expire_links(
+        &self,
+        limit: i64,
+        deliveries: &(dyn for<'l> Fn(&'l LinkRecord) -> Vec<NewDelivery> + Sync),
+    ) -> Result<usize, MyError>;
+}
+
+pub async fn connect(config: &AppConfig) -> Result<Arc<dyn Store>, MyError> {
+    if config.database_url == MEMORY_URL {
+        return Ok(Arc::new(MemoryStore::default()));
+    }
+    Ok(Arc::new(
+        PgStore::connect(&config.database_url, config.pool_size).await?,
+    ))
+}
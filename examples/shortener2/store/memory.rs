@@ -0,0 +1,608 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use sqlx::types::Json;
+
+use super::{search_words, Inserted, LinkFilter, LinkRecord, NewLink, Report, Revision, Store};
+use crate::{
+    checker::CheckResult,
+    webhook::{Delivery, DeliveryOutcome, NewDelivery},
+    MyError,
+};
+
+//内存存储，语义与 PgStore 保持一致：同一域名下每个用户的普通短链接 url 唯一，id 冲突时不写入
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    inner: Mutex<Inner>,
+    seq: AtomicU64,
+}
+
+//(domain, id)
+type Key = (String, String);
+//(domain, owner, url)，普通短链接只在同一个用户的短链接中去重
+type UrlKey = (String, Option<String>, String);
+
+fn key(domain: &str, s: &str) -> Key {
+    (domain.to_string(), s.to_string())
+}
+
+fn url_key(domain: &str, owner: Option<&str>, url: &str) -> UrlKey {
+    (domain.to_string(), owner.map(String::from), url.to_string())
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    links: HashMap<Key, LinkRecord>,
+    ids_by_url: HashMap<UrlKey, String>,
+    //标题和描述中的词 -> 包含这个词的短链接 (domain, id)
+    words: HashMap<String, HashSet<Key>>,
+    reports: Vec<Report>,
+    last_report_id: i64,
+    revisions: Vec<Revision>,
+    last_revision_id: i64,
+    deliveries: Vec<Delivery>,
+    last_delivery_id: i64,
+    //已经生成过期事件的短链接
+    expiry_notified: HashSet<Key>,
+}
+
+impl Inner {
+    fn insert(&mut self, id: &str, link: &NewLink) -> Inserted {
+        if let Some(id) = self.shared_id(link) {
+            return Inserted {
+                id: id.clone(),
+                created: false,
+            };
+        }
+        let record = LinkRecord {
+            domain: link.domain.clone(),
+            id: id.to_string(),
+            url: link.url.clone(),
+            owner: Some(link.owner.clone()),
+            created_at: Utc::now(),
+            clicks: link.clicks,
+            interstitial: link.interstitial,
+            disabled_reason: link.disabled_reason.clone(),
+            disabled_at: link.disabled_at,
+            redirect_status: link.redirect_status as i16,
+            query_params: Json(link.query_params.clone()),
+            password_hash: link.password_hash.clone(),
+            max_clicks: link.max_clicks,
+            destinations: Json(link.destinations.clone()),
+            sticky: link.sticky,
+            title: link.title.clone(),
+            description: link.description.clone(),
+            tags: link.tags.clone(),
+            expires_at: link.expires_at,
+            check_status: None,
+            check_error: None,
+            checked_at: None,
+            check_failures: 0,
+            dead: false,
+            rules: Json(link.rules.clone()),
+        };
+        for word in link_words(&record) {
+            self.words
+                .entry(word)
+                .or_default()
+                .insert(key(&link.domain, id));
+        }
+        self.links.insert(key(&link.domain, id), record);
+        if link.is_shared() {
+            self.ids_by_url.insert(
+                url_key(&link.domain, Some(&link.owner), &link.url),
+                id.to_string(),
+            );
+        }
+        Inserted {
+            id: id.to_string(),
+            created: true,
+        }
+    }
+
+    fn enqueue(&mut self, deliveries: &[NewDelivery]) {
+        let now = Utc::now();
+        for d in deliveries {
+            self.last_delivery_id += 1;
+            self.deliveries.push(Delivery {
+                id: self.last_delivery_id,
+                webhook: d.webhook.clone(),
+                event_id: d.event_id.clone(),
+                kind: d.kind.clone(),
+                payload: Json(d.payload.clone()),
+                attempts: 0,
+                next_attempt_at: now,
+                last_error: None,
+                delivered_at: None,
+                failed_at: None,
+            });
+        }
+    }
+
+    //可以复用的普通短链接
+    fn shared_id(&self, link: &NewLink) -> Option<&String> {
+        link.is_shared()
+            .then(|| {
+                self.ids_by_url
+                    .get(&url_key(&link.domain, Some(&link.owner), &link.url))
+            })
+            .flatten()
+    }
+
+    //普通短链接的 url 已存在时沿用已有的 id，不占用新的 id
+    fn conflicts(&self, id: &str, link: &NewLink) -> bool {
+        self.shared_id(link).is_none() && self.links.contains_key(&key(&link.domain, id))
+    }
+
+    //q 中所有词都出现的短链接，q 中没有词时为空
+    fn search(&self, q: &str) -> HashSet<&Key> {
+        let mut found: Option<HashSet<&Key>> = None;
+        for word in search_words(q) {
+            let keys: HashSet<&Key> = self.words.get(&word).into_iter().flatten().collect();
+            found = Some(match found {
+                Some(found) => found.intersection(&keys).copied().collect(),
+                None => keys,
+            });
+        }
+        found.unwrap_or_default()
+    }
+
+    fn unindex_words(&mut self, link: &LinkRecord) {
+        let link_key = key(&link.domain, &link.id);
+        for word in link_words(link) {
+            if let Some(keys) = self.words.get_mut(&word) {
+                keys.remove(&link_key);
+                if keys.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    //只有普通短链接登记在 ids_by_url 中
+    fn unindex(&mut self, link: &LinkRecord) {
+        let url_key = url_key(&link.domain, link.owner.as_deref(), &link.url);
+        if link.is_shared() && self.ids_by_url.get(&url_key) == Some(&link.id) {
+            self.ids_by_url.remove(&url_key);
+        }
+    }
+}
+
+fn link_words(link: &LinkRecord) -> HashSet<String> {
+    [&link.title, &link.description]
+        .into_iter()
+        .flatten()
+        .flat_map(|text| search_words(text))
+        .collect()
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn insert_link(&self, id: &str, link: &NewLink) -> Result<Option<Inserted>, MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.conflicts(id, link) {
+            return Ok(None);
+        }
+        Ok(Some(inner.insert(id, link)))
+    }
+
+    async fn insert_links(
+        &self,
+        domain: &str,
+        ids: &[String],
+        urls: &[String],
+        owner: &str,
+    ) -> Result<Option<HashMap<String, Inserted>>, MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        let links: Vec<NewLink> = urls
+            .iter()
+            .map(|url| NewLink {
+                domain: domain.to_string(),
+                ..NewLink::new(url, owner)
+            })
+            .collect();
+        //同一批中的 id 也不能重复
+        let mut batch_ids = HashMap::new();
+        for (id, link) in ids.iter().zip(&links) {
+            if inner.conflicts(id, link) || batch_ids.insert(id, &link.url).is_some() {
+                return Ok(None);
+            }
+        }
+        Ok(Some(
+            ids.iter()
+                .zip(&links)
+                .map(|(id, link)| (link.url.clone(), inner.insert(id, link)))
+                .collect(),
+        ))
+    }
+
+    async fn next_sequence(&self, count: usize) -> Result<Vec<u64>, MyError> {
+        let start = self.seq.fetch_add(count as u64, Ordering::Relaxed) + 1;
+        Ok((start..start + count as u64).collect())
+    }
+
+    async fn get_link(&self, domain: &str, id: &str) -> Result<Option<LinkRecord>, MyError> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .links
+            .get(&key(domain, id))
+            .cloned())
+    }
+
+    async fn resolve_link(
+        &self,
+        domain: &str,
+        id: &str,
+        unlocked: bool,
+    ) -> Result<Option<LinkRecord>, MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        Ok(inner
+            .links
+            .get_mut(&key(domain, id))
+            .filter(|link| {
+                link.disabled_at.is_none()
+                    && !link.is_expired()
+                    && !link.is_exhausted()
+                    && (link.password_hash.is_none() || unlocked)
+            })
+            .map(|link| {
+                link.clicks += 1;
+                link.clone()
+            }))
+    }
+
+    async fn add_destination_click(
+        &self,
+        domain: &str,
+        id: &str,
+        index: usize,
+    ) -> Result<(), MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(d) = inner
+            .links
+            .get_mut(&key(domain, id))
+            .and_then(|link| link.destinations.get_mut(index))
+        {
+            d.clicks += 1;
+        }
+        Ok(())
+    }
+
+    fn stream_links(
+        &self,
+        domain: &str,
+        owner: Option<String>,
+    ) -> BoxStream<'static, Result<LinkRecord, MyError>> {
+        let inner = self.inner.lock().unwrap();
+        let mut links: Vec<LinkRecord> = inner
+            .links
+            .values()
+            .filter(|link| link.domain == domain && (owner.is_none() || link.owner == owner))
+            .cloned()
+            .collect();
+        links.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+        stream::iter(links.into_iter().map(Ok)).boxed()
+    }
+
+    async fn list_links(
+        &self,
+        domain: &str,
+        owner: &str,
+        filter: &LinkFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<LinkRecord>, MyError> {
+        let inner = self.inner.lock().unwrap();
+        let candidates: Box<dyn Iterator<Item = &LinkRecord>> = match &filter.q {
+            Some(q) => Box::new(inner.search(q).into_iter().map(|k| &inner.links[k])),
+            None => Box::new(inner.links.values()),
+        };
+        let mut links: Vec<LinkRecord> = candidates
+            .filter(|link| link.domain == domain && link.owner.as_deref() == Some(owner))
+            .filter(|link| {
+                filter
+                    .tag
+                    .as_ref()
+                    .is_none_or(|tag| link.tags.contains(tag))
+            })
+            .filter(|link| filter.after.as_ref().is_none_or(|c| c.precedes(link)))
+            .cloned()
+            .collect();
+        links.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+        Ok(links
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn delete_link(&self, domain: &str, id: &str) -> Result<bool, MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.links.remove(&key(domain, id)) {
+            Some(link) => {
+                inner.unindex(&link);
+                inner.unindex_words(&link);
+                inner.expiry_notified.remove(&key(domain, id));
+                inner
+                    .reports
+                    .retain(|r| r.domain != domain || r.link_id != id);
+                inner
+                    .revisions
+                    .retain(|r| r.domain != domain || r.link_id != id);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn update_link(
+        &self,
+        domain: &str,
+        id: &str,
+        url: &str,
+        changed_by: &str,
+    ) -> Result<Option<LinkRecord>, MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        let link_key = key(domain, id);
+        let Some(old) = inner.links.get(&link_key).cloned() else {
+            return Ok(None);
+        };
+        if old.is_shared() {
+            let url_key = url_key(domain, old.owner.as_deref(), url);
+            if inner
+                .ids_by_url
+                .get(&url_key)
+                .is_some_and(|other| other != id)
+            {
+                return Err(MyError::Conflict(format!("url already shortened: {}", url)));
+            }
+            inner.unindex(&old);
+            inner.ids_by_url.insert(url_key, id.to_string());
+        }
+        if old.url != url {
+            inner.last_revision_id += 1;
+            let revision = Revision {
+                id: inner.last_revision_id,
+                domain: domain.to_string(),
+                link_id: id.to_string(),
+                old_url: old.url,
+                new_url: url.to_string(),
+                changed_by: changed_by.to_string(),
+                created_at: Utc::now(),
+            };
+            inner.revisions.push(revision);
+        }
+        let link = inner.links.get_mut(&link_key).unwrap();
+        if link.url != url {
+            //新的 url 需要重新检查
+            link.check_status = None;
+            link.check_error = None;
+            link.checked_at = None;
+            link.check_failures = 0;
+            link.dead = false;
+        }
+        link.url = url.to_string();
+        Ok(Some(link.clone()))
+    }
+
+    async fn list_revisions(
+        &self,
+        domain: &str,
+        id: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Revision>, MyError> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .revisions
+            .iter()
+            .rev()
+            .filter(|r| r.domain == domain && r.link_id == id)
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_revision(
+        &self,
+        domain: &str,
+        id: &str,
+        revision: i64,
+    ) -> Result<Option<Revision>, MyError> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .revisions
+            .iter()
+            .find(|r| r.domain == domain && r.link_id == id && r.id == revision)
+            .cloned())
+    }
+
+    async fn set_disabled(
+        &self,
+        domain: &str,
+        id: &str,
+        reason: Option<&str>,
+    ) -> Result<Option<LinkRecord>, MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        Ok(inner.links.get_mut(&key(domain, id)).map(|link| {
+            link.disabled_reason = reason.map(String::from);
+            link.disabled_at = reason.map(|_| Utc::now());
+            link.clone()
+        }))
+    }
+
+    async fn add_report(
+        &self,
+        domain: &str,
+        id: &str,
+        reason: &str,
+    ) -> Result<Option<Report>, MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.links.contains_key(&key(domain, id)) {
+            return Ok(None);
+        }
+        inner.last_report_id += 1;
+        let report = Report {
+            id: inner.last_report_id,
+            domain: domain.to_string(),
+            link_id: id.to_string(),
+            reason: reason.to_string(),
+            created_at: Utc::now(),
+        };
+        inner.reports.push(report.clone());
+        Ok(Some(report))
+    }
+
+    async fn list_reports(&self, limit: i64, offset: i64) -> Result<Vec<Report>, MyError> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .reports
+            .iter()
+            .rev()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn claim_checks(
+        &self,
+        limit: i64,
+        stale_before: DateTime<Utc>,
+    ) -> Result<Vec<LinkRecord>, MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut due: Vec<&mut LinkRecord> = inner
+            .links
+            .values_mut()
+            .filter(|link| link.disabled_reason.is_none())
+            .filter(|link| link.checked_at.is_none_or(|at| at < stale_before))
+            .collect();
+        //None 排在最前面
+        due.sort_by_key(|link| link.checked_at);
+        let now = Utc::now();
+        Ok(due
+            .into_iter()
+            .take(limit as usize)
+            .map(|link| {
+                link.checked_at = Some(now);
+                link.clone()
+            })
+            .collect())
+    }
+
+    async fn record_check(
+        &self,
+        domain: &str,
+        id: &str,
+        url: &str,
+        result: &CheckResult,
+        dead_after: u32,
+    ) -> Result<(), MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        let Some(link) = inner.links.get_mut(&key(domain, id)) else {
+            return Ok(());
+        };
+        if link.url != url {
+            return Ok(());
+        }
+        link.check_status = result.status.map(|s| s as i16);
+        link.check_error = result.error.clone();
+        link.checked_at = Some(Utc::now());
+        link.check_failures = match result.is_reachable() {
+            true => 0,
+            false => link.check_failures + 1,
+        };
+        link.dead = link.check_failures >= dead_after as i32;
+        Ok(())
+    }
+
+    async fn enqueue_deliveries(&self, deliveries: &[NewDelivery]) -> Result<(), MyError> {
+        self.inner.lock().unwrap().enqueue(deliveries);
+        Ok(())
+    }
+
+    async fn claim_deliveries(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> Result<Vec<Delivery>, MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Utc::now();
+        let leased_until: DateTime<Utc> =
+            now + chrono::Duration::milliseconds(lease.as_millis() as i64);
+        let mut due: Vec<&mut Delivery> = inner
+            .deliveries
+            .iter_mut()
+            .filter(|d| d.delivered_at.is_none() && d.failed_at.is_none())
+            .filter(|d| d.next_attempt_at <= now)
+            .collect();
+        due.sort_by_key(|d| (d.next_attempt_at, d.id));
+        Ok(due
+            .into_iter()
+            .take(limit as usize)
+            .map(|d| {
+                let claimed = d.clone();
+                d.next_attempt_at = leased_until;
+                claimed
+            })
+            .collect())
+    }
+
+    async fn finish_delivery(&self, id: i64, outcome: &DeliveryOutcome) -> Result<(), MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(d) = inner.deliveries.iter_mut().find(|d| d.id == id) {
+            d.attempts += 1;
+            match outcome {
+                DeliveryOutcome::Delivered => {
+                    d.delivered_at = Some(Utc::now());
+                    d.last_error = None;
+                }
+                DeliveryOutcome::Retry { at, error } => {
+                    d.next_attempt_at = *at;
+                    d.last_error = Some(error.clone());
+                }
+                DeliveryOutcome::Failed { error } => {
+                    d.failed_at = Some(Utc::now());
+                    d.last_error = Some(error.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn expire_links(
+        &self,
+        limit: i64,
+        deliveries: &(dyn for<'l> Fn(&'l LinkRecord) -> Vec<NewDelivery> + Sync),
+    ) -> Result<usize, MyError> {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Utc::now();
+        let expired: Vec<LinkRecord> = inner
+            .links
+            .iter()
+            .filter(|(k, link)| {
+                link.expires_at.is_some_and(|at| at <= now) && !inner.expiry_notified.contains(*k)
+            })
+            .take(limit as usize)
+            .map(|(_, link)| link.clone())
+            .collect();
+        for link in &expired {
+            inner.expiry_notified.insert(key(&link.domain, &link.id));
+            let pending = deliveries(link);
+            inner.enqueue(&pending);
+        }
+        Ok(expired.len())
+    }
+}
@@ -0,0 +1,100 @@
+//单元测试共用的辅助函数：使用内存存储的 DbState，以及通过 oneshot 直接调用 app(state) 发送请求
+//需要真实监听端口的测试见 e2e
+use std::sync::Arc;
+
+use axum::body::{to_bytes, Body};
+use http::{header::LOCATION, request, HeaderMap, Request, StatusCode};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::{auth::ApiKeyEntry, config::AppConfig, store::MemoryStore, DbState};
+
+//send 默认使用的 api key，属于 alice
+pub const KEY: &str = "key1";
+
+pub fn api_key(owner: &str, key: &str) -> ApiKeyEntry {
+    ApiKeyEntry {
+        owner: owner.to_string(),
+        key: key.to_string(),
+        admin: false,
+    }
+}
+
+//alice 使用 KEY，关闭限流，测试在此基础上修改其他配置
+pub fn config() -> AppConfig {
+    let mut config = AppConfig {
+        api_keys: vec![api_key("alice", KEY)],
+        ..AppConfig::default()
+    };
+    config.rate_limit.enabled = false;
+    config
+}
+
+pub fn state(config: AppConfig) -> Arc<DbState> {
+    Arc::new(DbState::with_store(config, Arc::new(MemoryStore::default())).unwrap())
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl TestResponse {
+    //响应体不是 JSON 时返回 Null
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap_or(Value::Null)
+    }
+
+    pub fn location(&self) -> Option<&str> {
+        self.headers.get(LOCATION).map(|v| v.to_str().unwrap())
+    }
+}
+
+//以 JSON 请求体发送请求
+pub async fn send(
+    state: &Arc<DbState>,
+    req: request::Builder,
+    body: Option<Value>,
+) -> TestResponse {
+    let body = body.map(|v| Body::from(v.to_string())).unwrap_or_default();
+    send_body(state, req.header("content-type", "application/json"), body).await
+}
+
+//带上 KEY 发送请求，请求中已经设置的 x-api-key 优先
+pub async fn send_body(
+    state: &Arc<DbState>,
+    mut req: request::Builder,
+    body: Body,
+) -> TestResponse {
+    if !req
+        .headers_ref()
+        .is_some_and(|h| h.contains_key("x-api-key"))
+    {
+        req = req.header("x-api-key", KEY);
+    }
+    let res = crate::app(state.clone())
+        .oneshot(req.body(body).unwrap())
+        .await
+        .unwrap();
+    let (parts, body) = res.into_parts();
+    let bytes = to_bytes(body, usize::MAX).await.unwrap();
+    TestResponse {
+        status: parts.status,
+        headers: parts.headers,
+        body: String::from_utf8(bytes.to_vec()).unwrap(),
+    }
+}
+
+//POST / 创建短链接，返回 id
+pub async fn create(state: &Arc<DbState>, body: Value) -> String {
+    let res = send(state, Request::post("/"), Some(body)).await;
+    assert_eq!(res.status, StatusCode::CREATED, "{}", res.body);
+    res.json()["url"]
+        .as_str()
+        .unwrap()
+        .rsplit('/')
+        .next()
+        .unwrap()
+        .to_string()
+}
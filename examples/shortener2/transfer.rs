@@ -0,0 +1,608 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use http::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    HeaderMap,
+};
+use serde::{Deserialize, Serialize};
+use url::form_urlencoded;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    abuse::DisableReason,
+    auth::AuthUser,
+    batch::{BatchError, BatchItem, BatchResponse, MAX_BATCH_SIZE},
+    error::AppQuery,
+    host::Host,
+    protect::check_password_hash,
+    store::LinkRecord,
+    DbState, LinkOptions, MyError,
+};
+
+const CSV: &str = "text/csv";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+}
+
+//导入时保留文件中的 id，还是按当前的 id 策略重新生成
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum IdMode {
+    #[default]
+    Preserve,
+    Regenerate,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    //不指定时根据 Content-Type 判断，text/csv 为 csv，其他为 json
+    format: Option<Format>,
+    #[serde(default)]
+    ids: IdMode,
+}
+
+//导入的一行，json 格式与导出的 LinkRecord 兼容，多余的字段会被忽略
+#[derive(Debug, Deserialize)]
+struct ImportRow {
+    id: Option<String>,
+    url: String,
+    //只有管理员导入时才使用文件中的 owner，否则归属于调用者
+    owner: Option<String>,
+    //保留导出时的过期时间，options 中的 ttl 优先
+    expires_at: Option<DateTime<Utc>>,
+    //保留导出时的点击数，已经用完 max_clicks 的短链接导入后仍然返回 410
+    #[serde(default)]
+    clicks: i64,
+    //管理员禁用的短链接导入后仍然禁用
+    disabled_reason: Option<DisableReason>,
+    disabled_at: Option<DateTime<Utc>>,
+    //受密码保护的短链接导入后密码不变，options 中的 password 优先
+    password_hash: Option<String>,
+    #[serde(flatten)]
+    options: LinkOptions,
+}
+
+//json 导出的一行，LinkRecord 不对外返回 password_hash，导出时单独带上
+#[derive(Debug, Serialize)]
+struct ExportRow<'a> {
+    #[serde(flatten)]
+    link: &'a LinkRecord,
+    password_hash: Option<&'a str>,
+}
+
+//csv 的一行，query_params 编码成 a=1&b=2 的形式，tags 用逗号分隔
+//destinations 和 rules 编码成 json，带有这两项或者 sticky 的行不能从 csv 导入，需要使用 json 格式
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvRow {
+    #[serde(default)]
+    id: Option<String>,
+    url: String,
+    #[serde(default)]
+    owner: Option<String>,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    clicks: Option<i64>,
+    #[serde(default)]
+    interstitial: Option<bool>,
+    #[serde(default)]
+    redirect_status: Option<u16>,
+    #[serde(default)]
+    query_params: Option<String>,
+    #[serde(default)]
+    max_clicks: Option<i64>,
+    #[serde(default)]
+    disabled_reason: Option<String>,
+    #[serde(default)]
+    disabled_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    tags: Option<String>,
+    #[serde(default)]
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    destinations: Option<String>,
+    #[serde(default)]
+    sticky: Option<bool>,
+    #[serde(default)]
+    rules: Option<String>,
+    #[serde(default)]
+    password_hash: Option<String>,
+}
+
+impl From<LinkRecord> for CsvRow {
+    fn from(link: LinkRecord) -> Self {
+        let query_params = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(link.query_params.iter())
+            .finish();
+        Self {
+            id: Some(link.id),
+            url: link.url,
+            owner: link.owner,
+            created_at: Some(link.created_at),
+            clicks: Some(link.clicks),
+            interstitial: Some(link.interstitial),
+            redirect_status: Some(link.redirect_status as u16),
+            query_params: Some(query_params),
+            max_clicks: link.max_clicks,
+            disabled_reason: link.disabled_reason,
+            disabled_at: link.disabled_at,
+            title: link.title,
+            description: link.description,
+            tags: Some(link.tags.join(",")),
+            expires_at: link.expires_at,
+            destinations: (!link.destinations.is_empty())
+                .then(|| serde_json::to_string(&link.destinations.0).unwrap_or_default()),
+            sticky: Some(link.sticky),
+            rules: (!link.rules.is_empty())
+                .then(|| serde_json::to_string(&link.rules.0).unwrap_or_default()),
+            password_hash: link.password_hash,
+        }
+    }
+}
+
+impl TryFrom<CsvRow> for ImportRow {
+    type Error = MyError;
+
+    fn try_from(row: CsvRow) -> Result<Self, Self::Error> {
+        let is_set = |v: &Option<String>| v.as_deref().is_some_and(|v| !v.trim().is_empty());
+        if is_set(&row.destinations) || is_set(&row.rules) || row.sticky == Some(true) {
+            return Err(MyError::BadRequest(
+                "links with destinations, sticky or rules cannot be imported from csv, use json"
+                    .to_string(),
+            ));
+        }
+        let query_params: BTreeMap<String, String> = row
+            .query_params
+            .map(|q| form_urlencoded::parse(q.as_bytes()).into_owned().collect())
+            .unwrap_or_default();
+        let max_clicks = row
+            .max_clicks
+            .map(|n| {
+                u32::try_from(n)
+                    .map_err(|_| MyError::BadRequest(format!("invalid max_clicks: {}", n)))
+            })
+            .transpose()?;
+        let disabled_reason = match row.disabled_reason.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some("abuse") => Some(DisableReason::Abuse),
+            Some("legal") => Some(DisableReason::Legal),
+            Some(reason) => {
+                return Err(MyError::BadRequest(format!(
+                    "invalid disabled_reason: {}",
+                    reason
+                )))
+            }
+        };
+        Ok(Self {
+            id: row.id,
+            url: row.url,
+            owner: row.owner,
+            expires_at: row.expires_at,
+            clicks: row.clicks.unwrap_or_default(),
+            disabled_reason,
+            disabled_at: row.disabled_at,
+            password_hash: row.password_hash.filter(|h| !h.is_empty()),
+            options: LinkOptions {
+                interstitial: row.interstitial.unwrap_or_default(),
+                redirect_status: row.redirect_status,
+                query_params,
+                password: None,
+                max_clicks,
+                title: row.title,
+                description: row.description,
+                tags: row
+                    .tags
+                    .map(|t| {
+                        t.split(',')
+                            .filter(|t| !t.trim().is_empty())
+                            .map(String::from)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                ..LinkOptions::default()
+            },
+        })
+    }
+}
+
+//GET /api/export?format=csv|json 逐行从数据库读取并写入响应，不会把所有行都加载到内存
+//只导出当前域名下的短链接，管理员导出全部，其他用户只导出自己的
+#[utoipa::path(
+    get,
+    path = "/api/export",
+    tag = "api",
+    params(ExportQuery),
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "all links of the caller (every link for admins)",
+            content(("application/json" = Vec<LinkRecord>), ("text/csv" = String))),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+    )
+)]
+pub async fn export_links(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    user: AuthUser,
+    AppQuery(query): AppQuery<ExportQuery>,
+) -> Result<Response, MyError> {
+    let owner = (!user.admin).then_some(user.owner);
+    let links = state.store.stream_links(&host.name, owner);
+    let (content_type, body) = match query.format {
+        Format::Json => {
+            //[ 第一行 , 第二行 ... ]
+            let rows = links.enumerate().map(|(i, link)| {
+                let link = link?;
+                let mut buf = if i == 0 { Vec::new() } else { b",\n".to_vec() };
+                let row = ExportRow {
+                    link: &link,
+                    password_hash: link.password_hash.as_deref(),
+                };
+                serde_json::to_writer(&mut buf, &row)
+                    .map_err(|e| MyError::Internal(format!("failed to encode link: {}", e)))?;
+                Ok::<_, MyError>(Bytes::from(buf))
+            });
+            let body = stream::once(async { Ok(Bytes::from_static(b"[\n")) })
+                .chain(rows)
+                .chain(stream::once(async { Ok(Bytes::from_static(b"\n]\n")) }));
+            ("application/json", Body::from_stream(body))
+        }
+        Format::Csv => {
+            //第一行之前写入表头
+            let rows = links.enumerate().map(|(i, link)| {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(i == 0)
+                    .from_writer(Vec::new());
+                writer
+                    .serialize(CsvRow::from(link?))
+                    .map_err(|e| MyError::Internal(format!("failed to encode link: {}", e)))?;
+                let buf = writer
+                    .into_inner()
+                    .map_err(|e| MyError::Internal(format!("failed to encode link: {}", e)))?;
+                Ok::<_, MyError>(Bytes::from(buf))
+            });
+            (CSV, Body::from_stream(rows))
+        }
+    };
+    let filename = match query.format {
+        Format::Json => "links.json",
+        Format::Csv => "links.csv",
+    };
+    Ok((
+        [
+            (CONTENT_TYPE, content_type.to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+//POST /api/import?format=csv|json&ids=preserve|regenerate 逐行导入，每一行单独返回结果
+//保留 id 时 id 已被占用，或者 url 已经以其他 id 存在，都作为该行的冲突返回
+#[utoipa::path(
+    post,
+    path = "/api/import",
+    tag = "api",
+    params(ImportQuery),
+    request_body(
+        content = Vec<LinkRecord>,
+        description = "rows in the export format, json array or csv with a header line",
+        content_type = "application/json",
+    ),
+    security(("bearer" = []), ("api_key" = [])),
+    responses(
+        (status = 200, description = "per-row results in file order", body = BatchResponse),
+        (status = 400, description = "body is not valid json or csv", body = ErrorBody),
+        (status = 401, description = "missing or invalid api key", body = ErrorBody),
+    )
+)]
+pub async fn import_links(
+    State(state): State<Arc<DbState>>,
+    host: Host,
+    user: AuthUser,
+    AppQuery(query): AppQuery<ImportQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, MyError> {
+    let format = query.format.unwrap_or_else(|| {
+        let is_csv = headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with(CSV));
+        if is_csv {
+            Format::Csv
+        } else {
+            Format::Json
+        }
+    });
+    let rows = parse_body(format, &body)?;
+    if rows.len() > MAX_BATCH_SIZE {
+        return Err(MyError::BadRequest(format!(
+            "import is larger than {} rows",
+            MAX_BATCH_SIZE
+        )));
+    }
+    let mut results = Vec::with_capacity(rows.len());
+    for (index, row) in rows.into_iter().enumerate() {
+        let ret = match row {
+            Ok(row) => import_row(&state, &host, &user, row, query.ids).await,
+            Err(e) => Err(e),
+        };
+        results.push(BatchItem::new(
+            &host,
+            index,
+            ret.map_err(|e| BatchError::from(&e)),
+        ));
+    }
+    Ok(Json(BatchResponse::new(results)))
+}
+
+async fn import_row(
+    state: &DbState,
+    host: &Host,
+    user: &AuthUser,
+    row: ImportRow,
+    mode: IdMode,
+) -> Result<String, MyError> {
+    let owner = match (user.admin, row.owner) {
+        (true, Some(owner)) if !owner.is_empty() => owner,
+        _ => user.owner.clone(),
+    };
+    let mut link = state.new_link(&host.name, &row.url, owner, row.options)?;
+    if link.expires_at.is_none() {
+        link.expires_at = row.expires_at;
+    }
+    if link.password_hash.is_none() {
+        if let Some(hash) = row.password_hash {
+            check_password_hash(&hash)?;
+            link.password_hash = Some(hash);
+        }
+    }
+    if row.clicks < 0 {
+        return Err(MyError::BadRequest(
+            "clicks must not be negative".to_string(),
+        ));
+    }
+    link.clicks = row.clicks;
+    if let Some(reason) = row.disabled_reason {
+        link.disabled_reason = Some(reason.as_str().to_string());
+        link.disabled_at = Some(row.disabled_at.unwrap_or_else(Utc::now));
+    }
+    match (mode, row.id) {
+        (IdMode::Preserve, Some(id)) if !id.is_empty() => state.shorten_as(&id, &link).await,
+        _ => state.shorten(&link).await,
+    }
+}
+
+//无法解析的行作为单行失败返回，不影响其他行
+fn parse_body(format: Format, body: &[u8]) -> Result<Vec<Result<ImportRow, MyError>>, MyError> {
+    match format {
+        Format::Json => {
+            let rows: Vec<serde_json::Value> = serde_json::from_slice(body)
+                .map_err(|e| MyError::BadRequest(format!("invalid import body: {}", e)))?;
+            Ok(rows
+                .into_iter()
+                .map(|row| {
+                    serde_json::from_value(row)
+                        .map_err(|e| MyError::BadRequest(format!("invalid row: {}", e)))
+                })
+                .collect())
+        }
+        Format::Csv => Ok(csv::Reader::from_reader(body)
+            .deserialize::<CsvRow>()
+            .map(|row| {
+                row.map_err(|e| MyError::BadRequest(format!("invalid row: {}", e)))
+                    .and_then(ImportRow::try_from)
+            })
+            .collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use http::{Request, StatusCode};
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::testing::{self, create, send, send_body};
+
+    async fn export(state: &Arc<DbState>, format: &str) -> String {
+        let res = send(
+            state,
+            Request::get(format!("/api/export?format={}", format)),
+            None,
+        )
+        .await;
+        assert_eq!(res.status, StatusCode::OK);
+        res.body
+    }
+
+    //导入到一个新的实例，返回每一行的结果
+    async fn import(format: &str, body: String) -> (Arc<DbState>, Vec<serde_json::Value>) {
+        let state = testing::state(testing::config());
+        let res = send_body(
+            &state,
+            Request::post(format!("/api/import?format={}", format)),
+            Body::from(body),
+        )
+        .await;
+        assert_eq!(res.status, StatusCode::OK, "{}", res.body);
+        let results = res.json()["results"].as_array().unwrap().clone();
+        (state, results)
+    }
+
+    async fn status(state: &Arc<DbState>, id: &str) -> StatusCode {
+        send(state, Request::get(format!("/{}", id)), None)
+            .await
+            .status
+    }
+
+    #[tokio::test]
+    async fn passwords_survive_a_round_trip() {
+        let state = testing::state(testing::config());
+        let id = create(
+            &state,
+            json!({ "url": "https://www.rust-lang.org/", "password": "hunter2" }),
+        )
+        .await;
+        for format in ["json", "csv"] {
+            let (imported, results) = import(format, export(&state, format).await).await;
+            assert!(results[0]["error"].is_null(), "{:?}", results);
+            let res = send(&imported, Request::get(format!("/{}", id)), None).await;
+            assert!(res.location().is_none(), "{}", format);
+            assert!(res.body.contains("Password required"), "{}", res.body);
+            for (password, status) in [
+                ("wrong", StatusCode::UNAUTHORIZED),
+                ("hunter2", StatusCode::SEE_OTHER),
+            ] {
+                let res = send_body(
+                    &imported,
+                    Request::post(format!("/{}", id))
+                        .header("content-type", "application/x-www-form-urlencoded"),
+                    Body::from(format!("password={}", password)),
+                )
+                .await;
+                assert_eq!(res.status, status, "{} {}", format, password);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_password_hashes_are_rejected() {
+        let (_, results) = import(
+            "json",
+            json!([{ "url": "https://www.rust-lang.org/", "password_hash": "plaintext" }])
+                .to_string(),
+        )
+        .await;
+        assert_eq!(results[0]["error"]["code"], "bad_request", "{:?}", results);
+    }
+
+    #[tokio::test]
+    async fn clicks_and_disabled_state_survive_a_round_trip() {
+        let state = testing::state(testing::config());
+        let used = create(
+            &state,
+            json!({ "url": "https://www.rust-lang.org/", "max_clicks": 1 }),
+        )
+        .await;
+        assert_eq!(status(&state, &used).await, StatusCode::FOUND);
+        let banned = create(&state, json!({ "url": "https://crates.io/" })).await;
+        state
+            .store
+            .set_disabled("", &banned, Some("legal"))
+            .await
+            .unwrap();
+
+        for format in ["json", "csv"] {
+            let (imported, results) = import(format, export(&state, format).await).await;
+            assert!(
+                results.iter().all(|r| r["error"].is_null()),
+                "{:?}",
+                results
+            );
+            assert_eq!(
+                status(&imported, &used).await,
+                StatusCode::GONE,
+                "{}",
+                format
+            );
+            assert_eq!(
+                status(&imported, &banned).await,
+                StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+                "{}",
+                format
+            );
+            let link = imported.store.get_link("", &used).await.unwrap().unwrap();
+            assert_eq!(link.clicks, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn csv_rejects_invalid_max_clicks() {
+        let csv =
+            "url,max_clicks\nhttps://a.com/,-1\nhttps://b.com/,5000000000\nhttps://c.com/,3\n";
+        let (state, results) = import("csv", csv.to_string()).await;
+        let codes: Vec<_> = results.iter().map(|r| &r["error"]["code"]).collect();
+        assert_eq!(
+            codes,
+            [&json!("bad_request"), &json!("bad_request"), &Value::Null]
+        );
+        let id = results[2]["id"].as_str().unwrap();
+        let link = state.store.get_link("", id).await.unwrap().unwrap();
+        assert_eq!(link.max_clicks, Some(3));
+    }
+
+    #[tokio::test]
+    async fn csv_rejects_links_it_cannot_represent() {
+        let state = testing::state(testing::config());
+        let split = create(
+            &state,
+            json!({
+                "url": "https://www.rust-lang.org/",
+                "destinations": [
+                    { "url": "https://www.rust-lang.org/" },
+                    { "url": "https://crates.io/" },
+                ],
+            }),
+        )
+        .await;
+        let routed = create(
+            &state,
+            json!({
+                "url": "https://docs.rs/",
+                "rules": [{ "url": "https://crates.io/", "devices": ["mobile"] }],
+            }),
+        )
+        .await;
+        create(&state, json!({ "url": "https://example.com/" })).await;
+
+        let (_, results) = import("csv", export(&state, "csv").await).await;
+        let failed: Vec<_> = results
+            .iter()
+            .filter(|r| !r["error"].is_null())
+            .map(|r| r["error"]["code"].as_str().unwrap())
+            .collect();
+        assert_eq!(failed, ["bad_request", "bad_request"], "{:?}", results);
+        //json 格式可以完整导入
+        let (imported, results) = import("json", export(&state, "json").await).await;
+        assert!(
+            results.iter().all(|r| r["error"].is_null()),
+            "{:?}",
+            results
+        );
+        for id in [&split, &routed] {
+            let link = imported.store.get_link("", id).await.unwrap().unwrap();
+            assert!(!link.is_shared(), "{}", id);
+        }
+    }
+}
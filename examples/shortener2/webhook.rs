@@ -0,0 +1,529 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use http::header::CONTENT_TYPE;
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use sqlx::{types::Json, FromRow};
+use tracing::warn;
+
+use crate::{
+    host::Host,
+    store::{LinkRecord, Store},
+    DbState,
+};
+
+//接收方用这些请求头校验和去重，签名为 sha256=<hex>，内容为 HMAC-SHA256(secret, "<timestamp>.<body>")
+pub const ID_HEADER: &str = "x-webhook-id";
+pub const EVENT_HEADER: &str = "x-webhook-event";
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+//领取的投递在请求超时之后再过这么久，其他实例才可以重新领取
+const LEASE_MARGIN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventKind {
+    #[serde(rename = "link.created")]
+    Created,
+    //跳转到了目标地址，只展示了警告页时不发送
+    #[serde(rename = "link.clicked")]
+    Clicked,
+    //ttl 到期或者达到 max_clicks
+    #[serde(rename = "link.expired")]
+    Expired,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Created => "link.created",
+            Self::Clicked => "link.clicked",
+            Self::Expired => "link.expired",
+        }
+    }
+}
+
+//一个 webhook 订阅
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    //投递队列按名字关联订阅，修改 url 或者 secret 后未完成的投递会发到新的地址
+    pub name: String,
+    pub url: String,
+    //签名用的共享密钥
+    pub secret: String,
+    //订阅的事件，为空表示全部
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+}
+
+impl WebhookConfig {
+    fn wants(&self, kind: EventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+//第 n 次失败后等待 initial_backoff_ms * 2^(n-1)，最长 max_backoff_secs，失败 max_attempts 次后放弃
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeliveryConfig {
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_secs: u64,
+    //队列中没有到期的投递时，隔多久再检查一次
+    pub poll_interval_ms: u64,
+    pub timeout_secs: u64,
+    //每轮最多领取的投递数，同一轮中的投递并发发送
+    pub batch_size: i64,
+}
+
+impl Default for DeliveryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 8,
+            initial_backoff_ms: 1000,
+            max_backoff_secs: 3600,
+            poll_interval_ms: 1000,
+            timeout_secs: 10,
+            batch_size: 50,
+        }
+    }
+}
+
+impl DeliveryConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("webhook_delivery.max_attempts: must be greater than 0".to_string());
+        }
+        if self.initial_backoff_ms == 0 || self.poll_interval_ms == 0 || self.timeout_secs == 0 {
+            return Err(
+                "webhook_delivery: initial_backoff_ms, poll_interval_ms and timeout_secs must be greater than 0"
+                    .to_string(),
+            );
+        }
+        if !(1..=1000).contains(&self.batch_size) {
+            return Err(format!(
+                "webhook_delivery.batch_size: must be between 1 and 1000, got {}",
+                self.batch_size
+            ));
+        }
+        Ok(())
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+        Duration::from_millis(self.initial_backoff_ms.saturating_mul(factor))
+            .min(Duration::from_secs(self.max_backoff_secs))
+    }
+}
+
+//待写入队列的一条投递
+#[derive(Debug, Clone)]
+pub struct NewDelivery {
+    pub webhook: String,
+    pub event_id: String,
+    pub kind: String,
+    pub payload: Value,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Delivery {
+    pub id: i64,
+    pub webhook: String,
+    pub event_id: String,
+    pub kind: String,
+    pub payload: Json<Value>,
+    //已经尝试的次数
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub failed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeliveryOutcome {
+    Delivered,
+    Retry { at: DateTime<Utc>, error: String },
+    //重试次数用尽，或者订阅已经从配置中删除
+    Failed { error: String },
+}
+
+pub struct Webhooks {
+    subscriptions: Vec<WebhookConfig>,
+    delivery: DeliveryConfig,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    pub fn new(subscriptions: Vec<WebhookConfig>, delivery: DeliveryConfig) -> Self {
+        Self {
+            subscriptions,
+            delivery,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.subscriptions.is_empty()
+    }
+
+    pub fn wants(&self, kind: EventKind) -> bool {
+        self.subscriptions.iter().any(|s| s.wants(kind))
+    }
+
+    //为订阅了 kind 的每个 webhook 生成一条投递，它们的事件 id 相同
+    pub fn deliveries(&self, kind: EventKind, data: Value) -> Vec<NewDelivery> {
+        let event_id = nanoid!();
+        let payload = json!({
+            "id": event_id,
+            "type": kind,
+            "created_at": Utc::now(),
+            "data": data,
+        });
+        self.subscriptions
+            .iter()
+            .filter(|s| s.wants(kind))
+            .map(|s| NewDelivery {
+                webhook: s.name.clone(),
+                event_id: event_id.clone(),
+                kind: kind.as_str().to_string(),
+                payload: payload.clone(),
+            })
+            .collect()
+    }
+
+    //写入队列失败只记录日志，不影响创建和跳转
+    pub async fn enqueue(&self, store: &dyn Store, deliveries: &[NewDelivery]) {
+        if deliveries.is_empty() {
+            return;
+        }
+        if let Err(e) = store.enqueue_deliveries(deliveries).await {
+            warn!(
+                "failed to enqueue {} webhook deliveries: {}",
+                deliveries.len(),
+                e
+            );
+        }
+    }
+
+    //发送一次，2xx 为成功，其他情况按退避时间重试
+    async fn deliver(&self, delivery: &Delivery) -> DeliveryOutcome {
+        let Some(sub) = self
+            .subscriptions
+            .iter()
+            .find(|s| s.name == delivery.webhook)
+        else {
+            return DeliveryOutcome::Failed {
+                error: format!("webhook {} is no longer configured", delivery.webhook),
+            };
+        };
+        let body = delivery.payload.0.to_string();
+        let timestamp = Utc::now().timestamp();
+        let ret = self
+            .client
+            .post(&sub.url)
+            .timeout(Duration::from_secs(self.delivery.timeout_secs))
+            .header(CONTENT_TYPE, "application/json")
+            .header(ID_HEADER, &delivery.event_id)
+            .header(EVENT_HEADER, &delivery.kind)
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(
+                SIGNATURE_HEADER,
+                sign(&sub.secret, timestamp, body.as_bytes()),
+            )
+            .body(body)
+            .send()
+            .await;
+        let error = match ret {
+            Ok(res) if res.status().is_success() => return DeliveryOutcome::Delivered,
+            Ok(res) => format!("endpoint returned {}", res.status()),
+            Err(e) => format!("request failed: {}", e),
+        };
+        let attempts = delivery.attempts as u32 + 1;
+        if attempts >= self.delivery.max_attempts {
+            warn!(
+                "giving up webhook {} event {} after {} attempts: {}",
+                delivery.webhook, delivery.event_id, attempts, error
+            );
+            return DeliveryOutcome::Failed { error };
+        }
+        let backoff = self.delivery.backoff(attempts);
+        DeliveryOutcome::Retry {
+            at: Utc::now() + chrono::Duration::milliseconds(backoff.as_millis() as i64),
+            error,
+        }
+    }
+}
+
+//事件中的短链接信息
+pub fn link_data(link: &LinkRecord, host: &Host) -> Value {
+    json!({
+        "domain": link.domain,
+        "id": link.id,
+        "short_url": host.short_url(&link.id),
+        "url": link.url,
+        "owner": link.owner,
+        "title": link.title,
+        "tags": link.tags,
+        "clicks": link.clicks,
+        "expires_at": link.expires_at,
+    })
+}
+
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+//后台投递任务，每一轮先为新过期的短链接生成事件，再领取到期的投递并发发送
+//投递保存在存储中，服务重启后从队列中继续
+pub async fn run(state: Arc<DbState>) {
+    let webhooks = &state.webhooks;
+    let config = &webhooks.delivery;
+    let lease = Duration::from_secs(config.timeout_secs) + LEASE_MARGIN;
+    let expired = |link: &LinkRecord| {
+        let mut data = link_data(link, &state.hosts.get(&link.domain));
+        data["reason"] = json!("ttl");
+        webhooks.deliveries(EventKind::Expired, data)
+    };
+    loop {
+        if webhooks.wants(EventKind::Expired) {
+            if let Err(e) = state.store.expire_links(config.batch_size, &expired).await {
+                warn!("failed to collect expired links: {}", e);
+            }
+        }
+        let due = match state.store.claim_deliveries(config.batch_size, lease).await {
+            Ok(due) => due,
+            Err(e) => {
+                warn!("failed to claim webhook deliveries: {}", e);
+                Vec::new()
+            }
+        };
+        let count = due.len() as i64;
+        join_all(due.iter().map(|delivery| async {
+            let outcome = webhooks.deliver(delivery).await;
+            if let Err(e) = state.store.finish_delivery(delivery.id, &outcome).await {
+                warn!("failed to record webhook delivery {}: {}", delivery.id, e);
+            }
+        }))
+        .await;
+        //没有领满说明暂时没有更多到期的投递
+        if count < config.batch_size {
+            tokio::time::sleep(Duration::from_millis(config.poll_interval_ms)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{extract::State, routing::post, Router};
+    use http::{HeaderMap, Request, StatusCode};
+    use tokio::{net::TcpListener, sync::mpsc, time::timeout};
+
+    use super::*;
+    use crate::{
+        config::AppConfig,
+        testing::{self, create, send},
+    };
+
+    const SECRET: &str = "s3cret";
+
+    type ReceiverState = (mpsc::UnboundedSender<(HeaderMap, String)>, Arc<AtomicUsize>);
+
+    //本地的接收方，记录收到的请求头和请求体，前 failures 次返回 500
+    struct Receiver {
+        url: String,
+        rx: mpsc::UnboundedReceiver<(HeaderMap, String)>,
+    }
+
+    impl Receiver {
+        async fn start(failures: usize) -> Self {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let failures = Arc::new(AtomicUsize::new(failures));
+            let app = Router::new()
+                .route(
+                    "/hook",
+                    post(
+                        |State((tx, failures)): State<ReceiverState>,
+                         headers: HeaderMap,
+                         body: String| async move {
+                            tx.send((headers, body)).unwrap();
+                            let failing = failures
+                                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                                    n.checked_sub(1)
+                                })
+                                .is_ok();
+                            match failing {
+                                true => StatusCode::INTERNAL_SERVER_ERROR,
+                                false => StatusCode::NO_CONTENT,
+                            }
+                        },
+                    ),
+                )
+                .with_state((tx, failures));
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/hook", listener.local_addr().unwrap());
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+            Self { url, rx }
+        }
+
+        async fn next(&mut self) -> (HeaderMap, Value) {
+            let (headers, body) = timeout(Duration::from_secs(5), self.rx.recv())
+                .await
+                .expect("webhook not delivered in time")
+                .unwrap();
+            //签名使用请求头中的时间戳和原始请求体
+            let timestamp: i64 = headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+            assert_eq!(
+                headers[SIGNATURE_HEADER].to_str().unwrap(),
+                sign(SECRET, timestamp, body.as_bytes())
+            );
+            (headers, serde_json::from_str(&body).unwrap())
+        }
+
+        async fn assert_idle(&mut self) {
+            let ret = timeout(Duration::from_millis(300), self.rx.recv()).await;
+            assert!(ret.is_err(), "unexpected delivery: {:?}", ret);
+        }
+    }
+
+    fn state(receiver: &Receiver, events: Vec<EventKind>) -> Arc<DbState> {
+        testing::state(AppConfig {
+            webhooks: vec![WebhookConfig {
+                name: "test".to_string(),
+                url: receiver.url.clone(),
+                secret: SECRET.to_string(),
+                events,
+            }],
+            webhook_delivery: DeliveryConfig {
+                max_attempts: 3,
+                initial_backoff_ms: 20,
+                poll_interval_ms: 20,
+                timeout_secs: 5,
+                ..DeliveryConfig::default()
+            },
+            ..testing::config()
+        })
+    }
+
+    #[tokio::test]
+    async fn queued_events_are_signed_and_delivered() {
+        let mut receiver = Receiver::start(0).await;
+        let state = state(&receiver, Vec::new());
+        //投递任务启动之前的事件保存在队列中
+        let id = create(&state, json!({ "url": "https://www.rust-lang.org/" })).await;
+        send(&state, Request::get(format!("/{}", id)), None).await;
+        //同一个 url 沿用已有的短链接，不产生新的事件
+        create(&state, json!({ "url": "https://www.rust-lang.org/" })).await;
+        tokio::spawn(run(state.clone()));
+
+        let mut events = [receiver.next().await, receiver.next().await];
+        events.sort_by_key(|(_, body)| body["type"].as_str().unwrap().to_string());
+        let (headers, clicked) = &events[0];
+        assert_eq!(headers[EVENT_HEADER], "link.clicked");
+        assert_eq!(headers[ID_HEADER].to_str().unwrap(), clicked["id"]);
+        assert_eq!(clicked["data"]["id"], id.as_str());
+        assert_eq!(clicked["data"]["destination"], "https://www.rust-lang.org/");
+        assert_eq!(clicked["data"]["clicks"], 1);
+        let (headers, created) = &events[1];
+        assert_eq!(headers[EVENT_HEADER], "link.created");
+        assert_eq!(created["data"]["owner"], "alice");
+        assert_eq!(
+            created["data"]["short_url"],
+            format!("http://localhost:9876/{}", id)
+        );
+        receiver.assert_idle().await;
+    }
+
+    #[tokio::test]
+    async fn warning_pages_are_not_reported_as_clicks() {
+        let mut receiver = Receiver::start(0).await;
+        let state = state(&receiver, vec![EventKind::Clicked]);
+        tokio::spawn(run(state.clone()));
+        let warned = create(
+            &state,
+            json!({ "url": "https://www.rust-lang.org/", "interstitial": true }),
+        )
+        .await;
+        let redirected = create(&state, json!({ "url": "https://crates.io/" })).await;
+        for id in [&warned, &redirected] {
+            send(&state, Request::get(format!("/{}", id)), None).await;
+        }
+
+        let (_, event) = receiver.next().await;
+        assert_eq!(event["data"]["id"], redirected.as_str());
+        receiver.assert_idle().await;
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_are_retried_with_the_same_event_id() {
+        let mut receiver = Receiver::start(2).await;
+        let state = state(&receiver, vec![EventKind::Created]);
+        tokio::spawn(run(state.clone()));
+        create(&state, json!({ "url": "https://www.rust-lang.org/" })).await;
+
+        let (first, _) = receiver.next().await;
+        for _ in 0..2 {
+            let (headers, _) = receiver.next().await;
+            assert_eq!(headers[ID_HEADER], first[ID_HEADER]);
+        }
+        receiver.assert_idle().await;
+    }
+
+    #[tokio::test]
+    async fn deliveries_give_up_after_max_attempts() {
+        let mut receiver = Receiver::start(usize::MAX).await;
+        let state = state(&receiver, vec![EventKind::Created]);
+        tokio::spawn(run(state.clone()));
+        create(&state, json!({ "url": "https://www.rust-lang.org/" })).await;
+
+        for _ in 0..3 {
+            receiver.next().await;
+        }
+        receiver.assert_idle().await;
+    }
+
+    #[tokio::test]
+    async fn expired_links_are_reported_once() {
+        let mut receiver = Receiver::start(0).await;
+        let state = state(&receiver, vec![EventKind::Expired]);
+        tokio::spawn(run(state.clone()));
+        let ttl = create(
+            &state,
+            json!({ "url": "https://www.rust-lang.org/", "ttl": 1 }),
+        )
+        .await;
+        let limited = create(
+            &state,
+            json!({ "url": "https://crates.io/", "max_clicks": 1 }),
+        )
+        .await;
+        send(&state, Request::get(format!("/{}", limited)), None).await;
+
+        let (_, event) = receiver.next().await;
+        assert_eq!(event["type"], "link.expired");
+        assert_eq!(event["data"]["id"], limited.as_str());
+        assert_eq!(event["data"]["reason"], "max_clicks");
+        let (_, event) = receiver.next().await;
+        assert_eq!(event["data"]["id"], ttl.as_str());
+        assert_eq!(event["data"]["reason"], "ttl");
+        receiver.assert_idle().await;
+    }
+}
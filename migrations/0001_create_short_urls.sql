@@ -0,0 +1,5 @@
+-- 短链接表，IF NOT EXISTS 兼容之前由程序启动时自动建表的数据库
+CREATE TABLE IF NOT EXISTS short_urls (
+    id CHAR(6) PRIMARY KEY,
+    url TEXT NOT NULL UNIQUE
+);
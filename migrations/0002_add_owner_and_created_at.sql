@@ -0,0 +1,4 @@
+-- 链接归属人和创建时间
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS owner TEXT;
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+CREATE INDEX IF NOT EXISTS short_urls_owner_created_at_idx ON short_urls (owner, created_at DESC);
@@ -0,0 +1,8 @@
+-- examples/shortener.rs 之前使用的是 urls 表，把其中的数据合并到 short_urls
+DO $$
+BEGIN
+    IF to_regclass('urls') IS NOT NULL THEN
+        INSERT INTO short_urls (id, url) SELECT id, url FROM urls ON CONFLICT DO NOTHING;
+    END IF;
+END
+$$;
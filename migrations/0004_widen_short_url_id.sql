@@ -0,0 +1,2 @@
+-- id 长度可配置，不再固定为 6 个字符
+ALTER TABLE short_urls ALTER COLUMN id TYPE VARCHAR(32);
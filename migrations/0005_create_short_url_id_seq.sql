@@ -0,0 +1,2 @@
+-- id_strategy = "sequence" 时使用的序列号
+CREATE SEQUENCE IF NOT EXISTS short_url_id_seq;
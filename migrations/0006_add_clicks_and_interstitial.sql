@@ -0,0 +1,3 @@
+-- 点击数和是否在跳转前展示警告页
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS clicks BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS interstitial BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,12 @@
+-- 管理员禁用的链接不再跳转，disabled_reason 为 abuse 或 legal
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS disabled_reason TEXT;
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS disabled_at TIMESTAMPTZ;
+
+-- 用户提交的滥用举报
+CREATE TABLE IF NOT EXISTS link_reports (
+    id BIGSERIAL PRIMARY KEY,
+    link_id VARCHAR(32) NOT NULL REFERENCES short_urls (id) ON DELETE CASCADE,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS link_reports_created_at_idx ON link_reports (created_at DESC);
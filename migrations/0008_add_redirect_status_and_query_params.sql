@@ -0,0 +1,3 @@
+-- 每个短链接单独配置跳转状态码，以及跳转时追加到目标地址的参数（例如 utm_source）
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS redirect_status SMALLINT NOT NULL DEFAULT 302;
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS query_params JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,8 @@
+-- 密码保护的短链接（argon2 哈希）和最多可以跳转的次数
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS password_hash TEXT;
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS max_clicks BIGINT;
+
+-- 受保护或者限制次数的短链接不参与按 url 去重，url 只在普通短链接之间唯一
+ALTER TABLE short_urls DROP CONSTRAINT IF EXISTS short_urls_url_key;
+CREATE UNIQUE INDEX IF NOT EXISTS short_urls_url_shared_idx ON short_urls (url)
+    WHERE password_hash IS NULL AND max_clicks IS NULL;
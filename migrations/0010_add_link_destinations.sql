@@ -0,0 +1,9 @@
+-- 多目标（A/B 测试）短链接，destinations 为 [{"url", "weight", "clicks"}]，为空表示普通短链接
+-- sticky 为 true 时同一访客通过 cookie 固定访问同一个目标
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS destinations JSONB NOT NULL DEFAULT '[]';
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS sticky BOOLEAN NOT NULL DEFAULT false;
+
+-- 多目标短链接也不参与按 url 去重
+DROP INDEX IF EXISTS short_urls_url_shared_idx;
+CREATE UNIQUE INDEX short_urls_url_shared_idx ON short_urls (url)
+    WHERE password_hash IS NULL AND max_clicks IS NULL AND destinations = '[]'::jsonb;
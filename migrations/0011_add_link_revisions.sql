@@ -0,0 +1,10 @@
+-- 每次修改短链接目标 url 时记录一条修订，回滚也作为一条新的修订
+CREATE TABLE IF NOT EXISTS link_revisions (
+    id BIGSERIAL PRIMARY KEY,
+    link_id VARCHAR(32) NOT NULL REFERENCES short_urls (id) ON DELETE CASCADE,
+    old_url TEXT NOT NULL,
+    new_url TEXT NOT NULL,
+    changed_by TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS link_revisions_link_id_idx ON link_revisions (link_id, id DESC);
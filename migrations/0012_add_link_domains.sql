@@ -0,0 +1,24 @@
+-- 多个品牌域名共用一个服务，每个域名有独立的 id 空间，默认域名为空字符串
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS domain TEXT NOT NULL DEFAULT '';
+ALTER TABLE link_reports ADD COLUMN IF NOT EXISTS domain TEXT NOT NULL DEFAULT '';
+ALTER TABLE link_revisions ADD COLUMN IF NOT EXISTS domain TEXT NOT NULL DEFAULT '';
+
+-- 主键改为 (domain, id)，引用它的外键也带上 domain
+ALTER TABLE link_reports DROP CONSTRAINT IF EXISTS link_reports_link_id_fkey;
+ALTER TABLE link_revisions DROP CONSTRAINT IF EXISTS link_revisions_link_id_fkey;
+ALTER TABLE short_urls DROP CONSTRAINT short_urls_pkey;
+ALTER TABLE short_urls ADD PRIMARY KEY (domain, id);
+ALTER TABLE link_reports ADD CONSTRAINT link_reports_link_fkey FOREIGN KEY (domain, link_id)
+    REFERENCES short_urls (domain, id) ON DELETE CASCADE;
+ALTER TABLE link_revisions ADD CONSTRAINT link_revisions_link_fkey FOREIGN KEY (domain, link_id)
+    REFERENCES short_urls (domain, id) ON DELETE CASCADE;
+DROP INDEX IF EXISTS link_revisions_link_id_idx;
+CREATE INDEX link_revisions_link_idx ON link_revisions (domain, link_id, id DESC);
+
+-- 按 url 去重也只在同一个域名内
+DROP INDEX IF EXISTS short_urls_url_shared_idx;
+CREATE UNIQUE INDEX short_urls_url_shared_idx ON short_urls (domain, url)
+    WHERE password_hash IS NULL AND max_clicks IS NULL AND destinations = '[]'::jsonb;
+
+DROP INDEX IF EXISTS short_urls_owner_created_at_idx;
+CREATE INDEX short_urls_owner_created_at_idx ON short_urls (domain, owner, created_at DESC);
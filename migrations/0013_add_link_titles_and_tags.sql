@@ -0,0 +1,16 @@
+-- 标题、描述和标签，用于查找自己创建过的短链接
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS title TEXT;
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS description TEXT;
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
+
+-- 标题和描述的全文索引，使用 simple 配置，不做词干处理，与内存存储的分词方式一致
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS search tsvector
+    GENERATED ALWAYS AS (to_tsvector('simple', coalesce(title, '') || ' ' || coalesce(description, ''))) STORED;
+CREATE INDEX IF NOT EXISTS short_urls_search_idx ON short_urls USING GIN (search);
+CREATE INDEX IF NOT EXISTS short_urls_tags_idx ON short_urls USING GIN (tags);
+
+-- 带标题、描述或标签的短链接属于创建者自己，不参与按 url 去重
+DROP INDEX IF EXISTS short_urls_url_shared_idx;
+CREATE UNIQUE INDEX short_urls_url_shared_idx ON short_urls (domain, url)
+    WHERE password_hash IS NULL AND max_clicks IS NULL AND destinations = '[]'::jsonb
+        AND title IS NULL AND description IS NULL AND tags = '{}';
@@ -0,0 +1,8 @@
+-- 短链接的过期时间，创建时由 ttl 计算，过期后跳转返回 410
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ;
+
+-- 会过期的短链接不参与按 url 去重，否则其他人得到的短链接可能提前失效
+DROP INDEX IF EXISTS short_urls_url_shared_idx;
+CREATE UNIQUE INDEX short_urls_url_shared_idx ON short_urls (domain, url)
+    WHERE password_hash IS NULL AND max_clicks IS NULL AND destinations = '[]'::jsonb
+        AND title IS NULL AND description IS NULL AND tags = '{}' AND expires_at IS NULL;
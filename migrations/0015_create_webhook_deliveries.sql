@@ -0,0 +1,24 @@
+-- webhook 投递队列（outbox），每个订阅的每个事件一行，服务重启后未完成的投递会继续
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    -- 订阅名，对应配置中 [[webhooks]] 的 name
+    webhook TEXT NOT NULL,
+    event_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    -- 完整的事件内容，发送时序列化为请求体并签名
+    payload JSONB NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error TEXT,
+    delivered_at TIMESTAMPTZ,
+    -- 重试次数用尽后放弃
+    failed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_due_idx ON webhook_deliveries (next_attempt_at)
+    WHERE delivered_at IS NULL AND failed_at IS NULL;
+
+-- 过期事件只发送一次
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS expiry_notified BOOLEAN NOT NULL DEFAULT false;
+CREATE INDEX IF NOT EXISTS short_urls_expiry_idx ON short_urls (expires_at)
+    WHERE expires_at IS NOT NULL AND NOT expiry_notified;
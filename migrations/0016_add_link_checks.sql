@@ -0,0 +1,11 @@
+-- 目标地址可达性检查的结果，check_status 为空且 check_error 不为空表示请求失败（超时、连接失败等）
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS check_status SMALLINT;
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS check_error TEXT;
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS checked_at TIMESTAMPTZ;
+-- 连续检查失败的次数，达到 checker.dead_after 后标记为 dead，检查成功或者修改 url 后清零
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS check_failures INT NOT NULL DEFAULT 0;
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS dead BOOLEAN NOT NULL DEFAULT false;
+
+-- 检查任务按 checked_at 从旧到新领取，从未检查过的排在最前面
+CREATE INDEX IF NOT EXISTS short_urls_checked_at_idx ON short_urls (checked_at NULLS FIRST)
+    WHERE disabled_reason IS NULL;
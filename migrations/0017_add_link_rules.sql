@@ -0,0 +1,9 @@
+-- 按访客的国家、设备和语言跳转到不同目标的规则，按 priority 从高到低匹配，都不匹配时使用 url
+ALTER TABLE short_urls ADD COLUMN IF NOT EXISTS rules JSONB NOT NULL DEFAULT '[]'::jsonb;
+
+-- 带规则的短链接不参与按 url 去重
+DROP INDEX IF EXISTS short_urls_url_shared_idx;
+CREATE UNIQUE INDEX short_urls_url_shared_idx ON short_urls (domain, url)
+    WHERE password_hash IS NULL AND max_clicks IS NULL AND destinations = '[]'::jsonb
+        AND title IS NULL AND description IS NULL AND tags = '{}' AND expires_at IS NULL
+        AND rules = '[]'::jsonb;
@@ -0,0 +1,7 @@
+-- 展示警告页、使用其他跳转状态码或者追加参数的短链接跳转方式不同，不参与按 url 去重
+DROP INDEX IF EXISTS short_urls_url_shared_idx;
+CREATE UNIQUE INDEX short_urls_url_shared_idx ON short_urls (domain, url)
+    WHERE interstitial = false AND redirect_status = 302 AND query_params = '{}'::jsonb
+        AND password_hash IS NULL AND max_clicks IS NULL AND destinations = '[]'::jsonb
+        AND title IS NULL AND description IS NULL AND tags = '{}' AND expires_at IS NULL
+        AND rules = '[]'::jsonb;
@@ -0,0 +1,7 @@
+-- 普通短链接只在同一个用户的短链接中按 url 去重，修改或回滚自己的短链接不会影响其他用户
+DROP INDEX IF EXISTS short_urls_url_shared_idx;
+CREATE UNIQUE INDEX short_urls_url_shared_idx ON short_urls (domain, owner, url)
+    WHERE interstitial = false AND redirect_status = 302 AND query_params = '{}'::jsonb
+        AND password_hash IS NULL AND max_clicks IS NULL AND destinations = '[]'::jsonb
+        AND title IS NULL AND description IS NULL AND tags = '{}' AND expires_at IS NULL
+        AND rules = '[]'::jsonb;